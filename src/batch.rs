@@ -0,0 +1,414 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    clock::SimClock,
+    config::{ConfigError, SimConfig},
+    headless::HeadlessPlugin,
+    network::{metrics::Metrics, routing::RoutingPolicies, AntNetworkPlugin, RequestStats},
+    GameMode, GameState, HexagonMesh,
+};
+
+/// An app running the network simulation without a window or logger.
+/// Nothing is drawn and time only moves forward when it's updated, so several can be
+/// run one after the other, or side by side, in the same process.
+pub fn headless_app(config: SimConfig) -> App {
+    let mut app = App::new();
+    app.add_plugin(HeadlessPlugin { log: false })
+        .add_loopless_state(GameMode::AntNetwork)
+        .add_loopless_state(GameState::Play)
+        .init_resource::<HexagonMesh>()
+        .add_plugin(AntNetworkPlugin {
+            headless: true,
+            config,
+        });
+    app
+}
+
+/// results of a single run
+#[derive(Debug, Clone)]
+pub struct RunSummary {
+    pub seed: Option<u64>,
+    pub issued: u64,
+    pub delivered: u64,
+    pub failed: u64,
+    /// fraction of finished requests that were delivered
+    pub delivery_rate: f32,
+    /// hops from origin to target of every delivered request
+    pub mean_hops: Option<f32>,
+}
+
+impl RunSummary {
+    fn from_world(world: &World, seed: Option<u64>) -> Self {
+        let stats = world.resource::<RequestStats>();
+        let metrics = world.resource::<Metrics>();
+        Self {
+            seed,
+            issued: stats.issued,
+            delivered: stats.completed,
+            failed: stats.failed(),
+            delivery_rate: stats.success_rate(),
+            mean_hops: metrics.cumulative.hops.mean(),
+        }
+    }
+}
+
+/// runs the simulation for `duration` simulated seconds as fast as possible
+pub fn run_headless(config: SimConfig, duration: f32) -> RunSummary {
+    let seed = config.seed;
+    let mut app = headless_app(config);
+    // the first update only runs startup systems
+    app.update();
+    while app.world.resource::<SimClock>().elapsed_seconds() < duration {
+        app.update();
+    }
+    RunSummary::from_world(&app.world, seed)
+}
+
+/// values to set in the config, by dotted key i.e. `decision_weights.pheromone_pow`
+pub type Combination = BTreeMap<String, toml::Value>;
+
+/// A set of runs comparing different parameters, stored as toml.
+/// Every combination of the values in `grid`, merged with each entry of `list`,
+/// is run once per seed.
+///
+/// ```toml
+/// duration = 300
+/// seeds = [1, 2, 3]
+///
+/// [grid]
+/// "decision_weights.pheromone_pow" = [2.0, 3.0, 4.0]
+/// "pheromones.trail_step" = [0.05, 0.1]
+///
+/// [[list]]
+/// "decision_weights.distance_pow" = 1.0
+/// "decision_weights.visited_pow" = 2.0
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sweep {
+    /// simulated seconds each run lasts
+    pub duration: Option<f32>,
+    pub seeds: Option<Vec<u64>>,
+    /// seeds 0 to `runs` when `seeds` isn't given
+    pub runs: Option<u64>,
+    pub grid: BTreeMap<String, Vec<toml::Value>>,
+    pub list: Vec<Combination>,
+}
+
+impl Sweep {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let contents = fs::read_to_string(path)?;
+        let sweep: Self = toml::from_str(&contents)?;
+        sweep.validate()?;
+        Ok(sweep)
+    }
+
+    /// the combinations are checked against the config they're applied to when run
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        match self.duration {
+            Some(duration) if !duration.is_finite() || duration <= 0. => Err(ConfigError::Invalid(
+                vec![format!("duration must be positive (got {})", duration)],
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn seeds(&self) -> Vec<u64> {
+        match (&self.seeds, self.runs) {
+            (Some(seeds), _) => seeds.clone(),
+            (None, Some(runs)) => (0..runs).collect(),
+            (None, None) => vec![0],
+        }
+    }
+
+    pub fn combinations(&self) -> Vec<Combination> {
+        let mut combinations = vec![Combination::new()];
+        for (key, values) in &self.grid {
+            combinations = combinations
+                .into_iter()
+                .flat_map(|c| {
+                    values.iter().map(move |v| {
+                        let mut c = c.clone();
+                        c.insert(key.clone(), v.clone());
+                        c
+                    })
+                })
+                .collect();
+        }
+        if self.list.is_empty() {
+            return combinations;
+        }
+        combinations
+            .iter()
+            .flat_map(|c| {
+                self.list.iter().map(move |entry| {
+                    let mut c = c.clone();
+                    c.extend(entry.clone());
+                    c
+                })
+            })
+            .collect()
+    }
+
+    /// Runs every combination with every seed, calling `progress` before each run.
+    /// All combinations are checked against `base` before anything is run.
+    pub fn run(
+        &self,
+        base: &SimConfig,
+        duration: f32,
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<SweepResults, ConfigError> {
+        let combinations = self.combinations();
+        let configs = combinations
+            .iter()
+            .map(|combination| {
+                let mut config = base.clone();
+                // every run would write over the same file
+                config.export.path = None;
+                for (key, value) in combination {
+                    config.set(key, value.clone())?;
+                }
+                // runs are headless apps with nothing but the built in policies
+                config.check_policy(&RoutingPolicies::default())?;
+                Ok(config)
+            })
+            .collect::<Result<Vec<_>, ConfigError>>()?;
+
+        let seeds = self.seeds();
+        let total = configs.len() * seeds.len();
+        let mut keys: Vec<String> = combinations
+            .iter()
+            .flat_map(|c| c.keys())
+            .cloned()
+            .collect();
+        keys.sort();
+        keys.dedup();
+        let mut rows = Vec::with_capacity(configs.len());
+        for (combination, config) in combinations.into_iter().zip(configs) {
+            let mut runs = Vec::with_capacity(seeds.len());
+            for &seed in &seeds {
+                progress(rows.len() * seeds.len() + runs.len(), total);
+                let config = SimConfig {
+                    seed: Some(seed),
+                    ..config.clone()
+                };
+                runs.push(run_headless(config, duration));
+            }
+            rows.push(SweepRow { combination, runs });
+        }
+        Ok(SweepResults { keys, rows })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SweepRow {
+    pub combination: Combination,
+    /// one per seed
+    pub runs: Vec<RunSummary>,
+}
+
+impl SweepRow {
+    fn mean(&self, f: impl Fn(&RunSummary) -> Option<f32>) -> Option<f32> {
+        let values: Vec<f32> = self.runs.iter().filter_map(f).collect();
+        if values.is_empty() {
+            return None;
+        }
+        Some(values.iter().sum::<f32>() / values.len() as f32)
+    }
+
+    pub fn mean_delivered(&self) -> Option<f32> {
+        self.mean(|r| Some(r.delivered as f32))
+    }
+
+    pub fn mean_failed(&self) -> Option<f32> {
+        self.mean(|r| Some(r.failed as f32))
+    }
+
+    pub fn mean_delivery_rate(&self) -> Option<f32> {
+        self.mean(|r| Some(r.delivery_rate))
+    }
+
+    pub fn mean_hops(&self) -> Option<f32> {
+        self.mean(|r| r.mean_hops)
+    }
+}
+
+/// Quoted if it has to be, i.e. for an array like `[1, 2]`.
+/// Strings are written without their toml quotes.
+fn csv_field(value: &toml::Value) -> String {
+    let field = match value {
+        toml::Value::String(s) => s.clone(),
+        value => value.to_string(),
+    };
+    if field.contains(|c| matches!(c, ',' | '"' | '\n' | '\r')) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+/// one row per combination, averaged over its seeds
+#[derive(Debug, Clone)]
+pub struct SweepResults {
+    /// every key set by any combination
+    pub keys: Vec<String>,
+    pub rows: Vec<SweepRow>,
+}
+
+impl SweepResults {
+    pub fn write_csv(&self, out: &mut impl Write) -> io::Result<()> {
+        let fmt = |v: Option<f32>| v.map(|v| v.to_string()).unwrap_or_default();
+        for key in &self.keys {
+            write!(out, "{},", key)?;
+        }
+        writeln!(out, "runs,delivered,failed,delivery_rate,mean_hops")?;
+        for row in &self.rows {
+            for key in &self.keys {
+                match row.combination.get(key) {
+                    Some(value) => write!(out, "{},", csv_field(value))?,
+                    None => write!(out, ",")?,
+                }
+            }
+            writeln!(
+                out,
+                "{},{},{},{},{}",
+                row.runs.len(),
+                fmt(row.mean_delivered()),
+                fmt(row.mean_failed()),
+                fmt(row.mean_delivery_rate()),
+                fmt(row.mean_hops())
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sweep(contents: &str) -> Sweep {
+        toml::from_str(contents).unwrap()
+    }
+
+    #[test]
+    fn empty_sweep_has_one_combination() {
+        assert_eq!(Sweep::default().combinations(), vec![Combination::new()]);
+    }
+
+    #[test]
+    fn grid_is_every_combination() {
+        let combinations = sweep(
+            r#"
+            [grid]
+            "a" = [1, 2, 3]
+            "b" = [true, false]
+            "#,
+        )
+        .combinations();
+        assert_eq!(combinations.len(), 6);
+        for a in 1..=3 {
+            for b in [true, false] {
+                let expected = Combination::from([
+                    ("a".to_string(), toml::Value::Integer(a)),
+                    ("b".to_string(), toml::Value::Boolean(b)),
+                ]);
+                assert!(combinations.contains(&expected), "missing {:?}", expected);
+            }
+        }
+    }
+
+    #[test]
+    fn list_is_merged_with_the_grid() {
+        let combinations = sweep(
+            r#"
+            [grid]
+            "a" = [1, 2]
+
+            [[list]]
+            "b" = 1
+
+            [[list]]
+            "b" = 2
+            "c" = 3
+            "#,
+        )
+        .combinations();
+        assert_eq!(combinations.len(), 4);
+        assert!(combinations.iter().all(|c| c.contains_key("a")));
+        assert_eq!(combinations.iter().filter(|c| c.len() == 3).count(), 2);
+    }
+
+    #[test]
+    fn list_entries_override_the_grid() {
+        let combinations = sweep(
+            r#"
+            [grid]
+            "a" = [1]
+
+            [[list]]
+            "a" = 2
+            "#,
+        )
+        .combinations();
+        assert_eq!(
+            combinations,
+            vec![Combination::from([(
+                "a".to_string(),
+                toml::Value::Integer(2)
+            )])]
+        );
+    }
+
+    #[test]
+    fn duration_has_to_be_positive() {
+        for duration in ["-5", "0", "inf", "nan"] {
+            let sweep = sweep(&format!("duration = {}", duration));
+            assert!(sweep.validate().is_err(), "duration = {}", duration);
+        }
+        assert!(sweep("duration = 30").validate().is_ok());
+        assert!(Sweep::default().validate().is_ok());
+    }
+
+    #[test]
+    fn csv_values_are_quoted() {
+        let results = SweepResults {
+            keys: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            rows: vec![SweepRow {
+                combination: Combination::from([
+                    ("a".to_string(), toml::Value::try_from([1, 2]).unwrap()),
+                    ("b".to_string(), toml::Value::from("say \"hi\"")),
+                    ("c".to_string(), toml::Value::from(0.5)),
+                ]),
+                runs: Vec::new(),
+            }],
+        };
+        let array = results.rows[0].combination["a"].to_string();
+        assert!(array.contains(','));
+        let mut out = Vec::new();
+        results.write_csv(&mut out).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "a,b,c,runs,delivered,failed,delivery_rate,mean_hops".to_string(),
+                format!("\"{}\",\"say \"\"hi\"\"\",0.5,0,,,,", array),
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_grid_values_give_no_combinations() {
+        assert!(sweep("[grid]\n\"a\" = []").combinations().is_empty());
+    }
+}
@@ -0,0 +1,123 @@
+use std::time::Duration;
+
+use bevy::{app::AppExit, prelude::*};
+use iyes_loopless::{fixedtimestep::FixedTimesteps, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::GameState;
+
+/// label of the fixed timestep stage every simulation system runs in
+pub const SIM_TICK: &str = "sim tick";
+
+const TICKS_PER_SECOND: f32 = 60.;
+
+/// Simulated time, advanced in fixed size ticks independent of the render frame rate.
+/// Anything that changes the state of the simulation should run once per tick and
+/// scale by `tick_length` rather than `Time::delta_seconds`.
+#[derive(Debug, Clone, Resource, Reflect, Serialize, Deserialize)]
+#[reflect(Resource)]
+pub struct SimClock {
+    /// simulated seconds per tick
+    pub tick_length: f32,
+    /// number of ticks simulated so far
+    pub tick: u64,
+}
+
+impl SimClock {
+    pub fn from_tick_rate(ticks_per_second: f32) -> Self {
+        Self {
+            tick_length: 1. / ticks_per_second,
+            tick: 0,
+        }
+    }
+
+    /// real time between ticks when running at normal speed
+    pub fn step(&self) -> Duration {
+        Duration::from_secs_f32(self.tick_length)
+    }
+
+    pub fn elapsed_seconds(&self) -> f32 {
+        self.tick as f32 * self.tick_length
+    }
+}
+
+impl Default for SimClock {
+    fn default() -> Self {
+        Self::from_tick_rate(TICKS_PER_SECOND)
+    }
+}
+
+/// simulated seconds to run for before exiting
+#[derive(Debug, Clone, Copy, Resource)]
+pub struct StopAfter(pub f32);
+
+pub fn stop_after(clock: Res<SimClock>, stop: Res<StopAfter>, mut exit: EventWriter<AppExit>) {
+    if clock.elapsed_seconds() >= stop.0 {
+        exit.send(AppExit);
+    }
+}
+
+/// speeds the simulation can be run at, relative to real time
+pub const TIME_SCALES: [f32; 7] = [0.25, 0.5, 1., 2., 4., 8., 16.];
+
+/// How many ticks are simulated per tick length of real time.
+/// Changes how often ticks happen, never how long a tick is, so results don't depend on it.
+#[derive(Debug, Clone, Copy, Resource, Deref, DerefMut, Reflect)]
+#[reflect(Resource)]
+pub struct TimeScale(pub f32);
+
+impl Default for TimeScale {
+    fn default() -> Self {
+        Self(1.)
+    }
+}
+
+/// ticks left to simulate while paused
+#[derive(Debug, Default, Resource, Deref, DerefMut, Reflect)]
+#[reflect(Resource)]
+pub struct PendingSteps(pub u32);
+
+/// run condition for everything in the sim tick stage
+pub fn sim_running(state: Res<CurrentState<GameState>>, steps: Res<PendingSteps>) -> bool {
+    state.0 == GameState::Play || steps.0 > 0
+}
+
+pub fn advance_clock(mut clock: ResMut<SimClock>, mut steps: ResMut<PendingSteps>) {
+    clock.tick += 1;
+    steps.0 = steps.0.saturating_sub(1);
+}
+
+pub fn apply_time_scale(
+    scale: Res<TimeScale>,
+    clock: Res<SimClock>,
+    mut timesteps: ResMut<FixedTimesteps>,
+) {
+    if !scale.is_changed() && !clock.is_changed() {
+        return;
+    }
+    if let Some(info) = timesteps.get_mut(SIM_TICK) {
+        info.step = clock.step().div_f32(scale.0.max(f32::EPSILON));
+    }
+}
+
+/// `-` / `=` to slow down / speed up, `.` to step a single tick while paused
+pub fn time_controls(
+    keys: Res<Input<KeyCode>>,
+    state: Res<CurrentState<GameState>>,
+    mut scale: ResMut<TimeScale>,
+    mut steps: ResMut<PendingSteps>,
+) {
+    let level = TIME_SCALES
+        .iter()
+        .position(|&s| s >= scale.0)
+        .unwrap_or(TIME_SCALES.len() - 1);
+    if keys.just_pressed(KeyCode::Minus) {
+        scale.0 = TIME_SCALES[level.saturating_sub(1)];
+    }
+    if keys.just_pressed(KeyCode::Equals) {
+        scale.0 = TIME_SCALES[(level + 1).min(TIME_SCALES.len() - 1)];
+    }
+    if keys.just_pressed(KeyCode::Period) && state.0 == GameState::Paused {
+        steps.0 += 1;
+    }
+}
@@ -0,0 +1,379 @@
+use std::{fmt, fs, io, path::Path};
+
+use bevy::prelude::World;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    network::{
+        export::ExportSettings, routing::RoutingPolicies, topology::TopologyParams,
+        DecisionWeights, NetworkParams, PheromoneParams, RequestLimits,
+    },
+    SimRng,
+};
+
+/// loaded at startup if it exists
+pub const DEFAULT_CONFIG_PATH: &str = "ant-sim.toml";
+
+/// Everything needed to set up an experiment, stored as toml.
+/// Any key that is left out keeps its default value.
+///
+/// ```toml
+/// seed = 42
+///
+/// [network]
+/// num_nests = 20
+///
+/// [decision_weights]
+/// pheromone_pow = 3.0
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SimConfig {
+    pub seed: Option<u64>,
+    pub network: NetworkParams,
+    pub decision_weights: DecisionWeights,
+    pub pheromones: PheromoneParams,
+    pub limits: RequestLimits,
+    pub topology: TopologyParams,
+    pub export: ExportSettings,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    /// one message per invalid value
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "couldn't read config: {}", e),
+            ConfigError::Parse(e) => write!(f, "couldn't parse config: {}", e),
+            ConfigError::Invalid(problems) => {
+                write!(f, "invalid config:")?;
+                for problem in problems {
+                    write!(f, "\n  {}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(e: toml::de::Error) -> Self {
+        ConfigError::Parse(e)
+    }
+}
+
+impl SimConfig {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let contents = fs::read_to_string(path)?;
+        Self::from_toml(&contents)
+    }
+
+    /// the config at [`DEFAULT_CONFIG_PATH`], or the defaults if there isn't one
+    pub fn load_default() -> Result<Self, ConfigError> {
+        if Path::new(DEFAULT_CONFIG_PATH).exists() {
+            Self::load(DEFAULT_CONFIG_PATH)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    /// The parameters a running simulation is currently using, with the seed it was started with.
+    /// Nothing is exported, so a run started from it doesn't write over the original's samples.
+    pub fn from_world(world: &World) -> Self {
+        Self {
+            seed: Some(world.resource::<SimRng>().seed()),
+            network: world.resource::<NetworkParams>().clone(),
+            decision_weights: world.resource::<DecisionWeights>().clone(),
+            pheromones: world.resource::<PheromoneParams>().clone(),
+            limits: world.resource::<RequestLimits>().clone(),
+            topology: world.resource::<TopologyParams>().clone(),
+            export: ExportSettings::default(),
+        }
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Self, ConfigError> {
+        let config: Self = toml::from_str(contents)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Sets a single value by its dotted key, i.e. `decision_weights.pheromone_pow`.
+    /// The config is left as it was if the key doesn't exist or the result is invalid.
+    pub fn set(&mut self, key: &str, value: toml::Value) -> Result<(), ConfigError> {
+        let mut root = toml::Value::try_from(&*self)
+            .map_err(|e| ConfigError::Invalid(vec![format!("couldn't set {}: {}", key, e)]))?;
+        let mut slot = &mut root;
+        for part in key.split('.') {
+            let table = slot.as_table_mut().ok_or_else(|| {
+                ConfigError::Invalid(vec![format!("{} is not a config key", key)])
+            })?;
+            slot = table
+                .entry(part)
+                .or_insert_with(|| toml::Value::Table(Default::default()));
+        }
+        *slot = value;
+        let config: Self = root.try_into()?;
+        config.validate()?;
+        *self = config;
+        Ok(())
+    }
+
+    /// Checks the routing policy is one of `policies`. Any plugin can register a policy,
+    /// so this is left out of [`SimConfig::validate`] and checked once the app is built.
+    pub fn check_policy(&self, policies: &RoutingPolicies) -> Result<(), ConfigError> {
+        let policy = &self.decision_weights.policy;
+        if policies.get(policy).is_some() {
+            return Ok(());
+        }
+        Err(ConfigError::Invalid(vec![format!(
+            "decision_weights.policy must be one of {:?} (got {:?})",
+            policies.names().collect::<Vec<_>>(),
+            policy
+        )]))
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+        let mut check = |ok: bool, key: &str, requirement: &str, value: &dyn fmt::Display| {
+            if !ok {
+                problems.push(format!("{} must be {} (got {})", key, requirement, value));
+            }
+        };
+
+        if let Some(seed) = self.seed {
+            // toml integers are signed
+            check(
+                seed <= i64::MAX as u64,
+                "seed",
+                &format!("at most {}", i64::MAX),
+                &seed,
+            );
+        }
+
+        let net = &self.network;
+        check(
+            net.num_nests >= 2,
+            "network.num_nests",
+            "at least 2",
+            &net.num_nests,
+        );
+        check(
+            net.max_ants >= 1,
+            "network.max_ants",
+            "at least 1",
+            &net.max_ants,
+        );
+        check(
+            net.ant_speed.is_finite() && net.ant_speed > 0.,
+            "network.ant_speed",
+            "positive",
+            &net.ant_speed,
+        );
+        check(
+            (0.0..=1.0).contains(&net.food_request_prob),
+            "network.food_request_prob",
+            "between 0 and 1",
+            &net.food_request_prob,
+        );
+        check(
+            net.border_padding.is_finite() && net.border_padding >= 0.,
+            "network.border_padding",
+            "0 or more",
+            &net.border_padding,
+        );
+        check(
+            net.pheromone_granularity >= 1,
+            "network.pheromone_granularity",
+            "at least 1",
+            &net.pheromone_granularity,
+        );
+
+        let weights = &self.decision_weights;
+        for (key, value) in [
+            ("decision_weights.distance_pow", weights.distance_pow),
+            ("decision_weights.pheromone_pow", weights.pheromone_pow),
+            ("decision_weights.visited_pow", weights.visited_pow),
+        ] {
+            check(value.is_finite(), key, "a finite number", &value);
+        }
+        let pher = &self.pheromones;
+        for (key, value) in [
+            ("pheromones.trail_step", pher.trail_step),
+            ("pheromones.nest_step", pher.nest_step),
+            ("pheromones.trail_fade_rate", pher.trail_fade_rate),
+            ("pheromones.nest_fade_rate", pher.nest_fade_rate),
+        ] {
+            check(value.is_finite() && value >= 0., key, "0 or more", &value);
+        }
+
+        check(
+            self.limits.ttl.is_finite() && self.limits.ttl >= 0.,
+            "limits.ttl",
+            "0 or more",
+            &self.limits.ttl,
+        );
+
+        let topology = &self.topology;
+        check(topology.k >= 1, "topology.k", "at least 1", &topology.k);
+        check(
+            topology.radius.is_finite() && topology.radius >= 0.,
+            "topology.radius",
+            "0 or more",
+            &topology.radius,
+        );
+        check(
+            (0.0..=1.0).contains(&topology.rewire_prob),
+            "topology.rewire_prob",
+            "between 0 and 1",
+            &topology.rewire_prob,
+        );
+
+        check(
+            self.export.interval.is_finite() && self.export.interval > 0.,
+            "export.interval",
+            "positive",
+            &self.export.interval,
+        );
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(problems))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::Vec2;
+
+    use crate::network::{ant::Ant, nest::Nest, routing::RoutingPolicy};
+
+    use super::*;
+
+    #[test]
+    fn set_changes_only_that_value() {
+        let mut config = SimConfig::default();
+        config
+            .set("decision_weights.pheromone_pow", toml::Value::Float(2.5))
+            .unwrap();
+        assert_eq!(config.decision_weights.pheromone_pow, 2.5);
+        assert_eq!(config.network, NetworkParams::default());
+
+        config.set("seed", toml::Value::Integer(7)).unwrap();
+        assert_eq!(config.seed, Some(7));
+    }
+
+    #[test]
+    fn set_leaves_config_alone_on_error() {
+        let mut config = SimConfig::default();
+        for (key, value) in [
+            ("network.no_such_key", toml::Value::Integer(1)),
+            ("no_such_table.key", toml::Value::Integer(1)),
+            ("network.num_nests.nested", toml::Value::Integer(1)),
+            ("network.num_nests", toml::Value::Integer(1)),
+            ("network.num_nests", toml::Value::String("ten".to_string())),
+        ] {
+            assert!(config.set(key, value).is_err(), "{} should fail", key);
+        }
+        assert_eq!(config.network, NetworkParams::default());
+    }
+
+    #[test]
+    fn set_with_unsigned_seed_fails_instead_of_panicking() {
+        let mut config = SimConfig {
+            seed: Some(u64::MAX),
+            ..SimConfig::default()
+        };
+        assert!(config
+            .set("decision_weights.pheromone_pow", toml::Value::Float(2.5))
+            .is_err());
+    }
+
+    #[test]
+    fn validate_accepts_defaults() {
+        assert!(SimConfig::default().validate().is_ok());
+    }
+
+    #[test]
+    fn validate_reports_every_problem() {
+        let mut config = SimConfig {
+            seed: Some(u64::MAX),
+            ..SimConfig::default()
+        };
+        config.network.num_nests = 1;
+        config.network.food_request_prob = 2.;
+        config.decision_weights.distance_pow = f32::NAN;
+        config.pheromones.trail_step = -1.;
+        config.topology.rewire_prob = 1.5;
+        config.export.interval = 0.;
+        match config.validate() {
+            Err(ConfigError::Invalid(problems)) => {
+                for key in [
+                    "seed",
+                    "network.num_nests",
+                    "network.food_request_prob",
+                    "decision_weights.distance_pow",
+                    "pheromones.trail_step",
+                    "topology.rewire_prob",
+                    "export.interval",
+                ] {
+                    assert!(
+                        problems.iter().any(|p| p.starts_with(key)),
+                        "{} should be reported in {:?}",
+                        key,
+                        problems
+                    );
+                }
+                assert_eq!(problems.len(), 7);
+            }
+            other => panic!("expected invalid config, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn policy_is_checked_against_the_registered_ones() {
+        struct Custom;
+
+        impl RoutingPolicy for Custom {
+            fn name(&self) -> &str {
+                "custom"
+            }
+
+            fn weights(
+                &self,
+                _ant: &Ant,
+                _loc: Vec2,
+                _current: &Nest,
+                candidates: &[&Nest],
+                _decision_weights: &DecisionWeights,
+            ) -> Vec<f32> {
+                vec![1.0; candidates.len()]
+            }
+        }
+
+        let mut config = SimConfig::default();
+        config.decision_weights.policy = "custom".to_string();
+        // only a typo as far as the config alone can tell
+        assert!(config.validate().is_ok());
+        let mut policies = RoutingPolicies::default();
+        assert!(config.check_policy(&policies).is_err());
+        policies.register(Custom);
+        assert!(config.check_policy(&policies).is_ok());
+        assert!(SimConfig::default().check_policy(&policies).is_ok());
+    }
+}
@@ -0,0 +1,46 @@
+use bevy::{log::LogPlugin, prelude::*, time::TimeUpdateStrategy};
+
+use crate::clock::SimClock;
+
+/// Everything the network simulation needs to run without a window, renderer or input.
+/// Add it instead of `DefaultPlugins` and insert a [`BoundingBox`](crate::BoundingBox)
+/// to control the size of the arena.
+///
+/// Time advances by exactly one simulation tick per update, so headless runs go as fast
+/// as the cpu allows instead of waiting on the wall clock.
+pub struct HeadlessPlugin {
+    /// set up the global logger, only one app per process can
+    pub log: bool,
+}
+
+impl Default for HeadlessPlugin {
+    fn default() -> Self {
+        Self { log: true }
+    }
+}
+
+impl Plugin for HeadlessPlugin {
+    fn build(&self, app: &mut App) {
+        if self.log {
+            app.add_plugin(LogPlugin::default());
+        }
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(AssetPlugin::default())
+            // sprites and meshes are still attached to entities, they just never get drawn
+            .add_asset::<Mesh>()
+            .add_asset::<ColorMaterial>()
+            .add_asset::<TextureAtlas>()
+            .init_resource::<SimClock>()
+            .add_system_to_stage(CoreStage::Last, lockstep_time);
+    }
+}
+
+/// sets the time the next update will see to exactly one tick after this one
+fn lockstep_time(
+    time: Res<Time>,
+    clock: Res<SimClock>,
+    mut update_strategy: ResMut<TimeUpdateStrategy>,
+) {
+    let last_update = time.last_update().unwrap_or_else(|| time.startup());
+    *update_strategy = TimeUpdateStrategy::ManualInstant(last_update + clock.step());
+}
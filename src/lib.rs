@@ -1,10 +1,23 @@
+use bevy::{
+    log,
+    window::{WindowId, WindowResized},
+};
 pub use bevy::{prelude::*, render::color::Color};
 pub use iyes_loopless::prelude::*;
+use network::nest::NestId;
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
 // pub mod ant;
 // pub mod food;
 // pub mod nest;
 // pub mod pheromones;
+pub mod batch;
+pub mod clock;
+pub mod config;
+pub mod headless;
 pub mod network;
+pub mod tune;
 // pub mod wander;
 
 const NEST_SPREAD: f32 = BORDER_PADDING;
@@ -28,10 +41,9 @@ pub const WINDOW_SIZE_Y: f32 = WINDOW_SIZE;
 
 pub const NUM_NESTS: usize = 15;
 pub const NEST_SIZE: f32 = 16.;
+// chance of each nest requesting food every tick
 pub const NEST_FOOD_REQUEST_PROB: f32 = 0.01;
 
-// TODO: make colors a resource
-// TODO: make colors have unique id so mutltiple nests of same color can exist
 // pub const NEST_COLORS: [Color; 10] = [
 //     Color::rgb(1.0, 0.745, 0.0431),
 //     Color::rgb(0.984, 0.337, 0.027),
@@ -76,64 +88,192 @@ pub const NEST_COLORS: [Color; 20] = [
 // allowed distance to edge of screen
 const BORDER_PADDING: f32 = 50.0;
 
+/// The palette nests are drawn with and which color each nest uses.
+/// Colors are only for display, nests are identified by their [`NestId`].
 #[derive(Resource, Reflect)]
 #[reflect(Resource)]
 pub struct Colors {
     pub colors: Vec<Color>,
-    pub color_ids: Vec<usize>,
     pub color_handles: Vec<Handle<ColorMaterial>>,
+    /// palette index of each nest, indexed by nest id
+    pub nest_colors: Vec<usize>,
 }
 
-use std::iter::{zip, Zip};
-use std::vec::IntoIter;
-
 impl Colors {
-    pub fn iter(&self) -> Zip<IntoIter<Color>, IntoIter<usize>> {
-        zip(self.colors.clone(), self.color_ids.clone())
+    pub fn color_of(&self, nest: NestId) -> usize {
+        self.nest_colors[nest.0]
+    }
+
+    pub fn handle_of(&self, nest: NestId) -> &Handle<ColorMaterial> {
+        &self.color_handles[self.color_of(nest)]
+    }
+
+    /// draws `nest` with its own color instead of one from the palette
+    pub fn set_color(&mut self, nest: NestId, color: Color, materials: &mut Assets<ColorMaterial>) {
+        self.colors.push(color);
+        self.color_handles
+            .push(materials.add(ColorMaterial::from(color)));
+        self.nest_colors[nest.0] = self.colors.len() - 1;
+    }
+
+    /// gives every nest without a color the next color in the palette
+    pub fn resize(&mut self, num_nests: usize) {
+        for nest in self.nest_colors.len()..num_nests {
+            self.nest_colors.push(nest % NEST_COLORS.len());
+        }
     }
 }
 
 impl FromWorld for Colors {
     fn from_world(world: &mut World) -> Self {
+        let num_nests = world.resource::<network::NetworkParams>().num_nests;
         let assets: &mut Mut<Assets<ColorMaterial>> = &mut world.resource_mut();
-        // TODO: turn this into a vec and make it dynamic
-        let mut colors: Vec<Color> = NEST_COLORS
-            .iter()
-            .cycle()
-            .take(NUM_NESTS)
-            .cloned()
-            .collect();
-        colors.truncate(NUM_NESTS);
-        let color_ids = (0..NUM_NESTS).collect();
+        let colors = NEST_COLORS.to_vec();
         let color_handles = colors
             .iter()
             .copied()
             .map(|c| assets.add(ColorMaterial::from(c)))
             .collect();
-        Colors {
+        let mut colors = Colors {
             colors,
-            color_ids,
             color_handles,
-        }
+            nest_colors: Vec::with_capacity(num_nests),
+        };
+        colors.resize(num_nests);
+        colors
     }
 }
 
 const MAX_ANTS: u32 = 50;
 
+/// number of ants currently out on a request, pooled ants don't count
 #[derive(Resource, Deref, DerefMut, Default, Reflect)]
 #[reflect(Resource)]
 pub struct NumAnts(u32);
 
+/// The single source of randomness for the simulation.
+/// Every random choice draws from this so a run can be reproduced from its seed.
 #[derive(Resource)]
+pub struct SimRng {
+    seed: u64,
+    rng: ChaCha8Rng,
+}
+
+impl SimRng {
+    pub fn seed_from_u64(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// how far into the stream of its seed the rng has got
+    pub fn word_pos(&self) -> u128 {
+        self.rng.get_word_pos()
+    }
+
+    /// picks up where an rng with the same seed was at `word_pos`
+    pub fn from_word_pos(seed: u64, word_pos: u128) -> Self {
+        let mut rng = Self::seed_from_u64(seed);
+        rng.rng.set_word_pos(word_pos);
+        rng
+    }
+}
+
+/// seeded from entropy, the seed is logged so the run can be repeated
+impl Default for SimRng {
+    fn default() -> Self {
+        let seed = rand::random();
+        log::info!("simulation seed: {}", seed);
+        Self::seed_from_u64(seed)
+    }
+}
+
+impl RngCore for SimRng {
+    fn next_u32(&mut self) -> u32 {
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.rng.try_fill_bytes(dest)
+    }
+}
+
+/// Dimensions of the world the simulation runs in, centered on the origin.
+/// This is the only thing that decides how big the world is, the window just shows it.
+#[derive(Debug, Clone, Copy, PartialEq, Resource, Reflect, Serialize, Deserialize)]
+#[reflect(Resource)]
 pub struct BoundingBox {
     pub w: f32,
     pub h: f32,
 }
 
-// TODO: impl FromWorld for dynamic BoundingBox
+impl BoundingBox {
+    pub fn size(&self) -> Vec2 {
+        Vec2 {
+            x: self.w,
+            y: self.h,
+        }
+    }
+}
+
 impl Default for BoundingBox {
     fn default() -> Self {
-        Self { w: 200.0, h: 200.0 }
+        Self {
+            w: WINDOW_SIZE_X,
+            h: WINDOW_SIZE_Y,
+        }
+    }
+}
+
+/// how the arena reacts to the window changing size
+#[derive(Debug, Clone, PartialEq, Resource, Reflect, Serialize, Deserialize)]
+#[reflect(Resource)]
+pub struct ArenaSettings {
+    /// resize the arena to match the primary window
+    pub follow_window: bool,
+    /// move the nests to fit the resized arena
+    pub relayout_nests: bool,
+}
+
+impl Default for ArenaSettings {
+    fn default() -> Self {
+        Self {
+            follow_window: true,
+            relayout_nests: true,
+        }
+    }
+}
+
+pub fn arena_follows_window(
+    mut resized: EventReader<WindowResized>,
+    settings: Res<ArenaSettings>,
+    params: Res<network::NetworkParams>,
+    mut bounding_box: ResMut<BoundingBox>,
+) {
+    let last_resize = resized
+        .iter()
+        .filter(|e| e.id == WindowId::primary())
+        .last();
+    // minimized windows report a size of zero, there's no arena to fit in that
+    let last_resize = last_resize.filter(|e| e.width.min(e.height) > 2. * params.border_padding);
+    if let (Some(event), true) = (last_resize, settings.follow_window) {
+        *bounding_box = BoundingBox {
+            w: event.width,
+            h: event.height,
+        };
     }
 }
 
@@ -1,45 +1,426 @@
+use std::{
+    fmt::Display,
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+};
+
 use ant_sim::*;
-use bevy::diagnostic::{
-    EntityCountDiagnosticsPlugin, FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin,
+use bevy::{
+    diagnostic::{EntityCountDiagnosticsPlugin, FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},
+    log,
+    render::camera::ScalingMode,
+};
+use clap::{Parser, Subcommand, ValueEnum};
+use network::{
+    dot,
+    export::{ExportFormat, Exporter},
+    import::ImportedNetwork,
+    replay,
+    routing::RoutingPolicies,
+    snapshot,
 };
 
+/// Ant colony simulation of request routing in peer-to-peer networks
+#[derive(Debug, Parser)]
+#[command(name = "ant-sim")]
+struct Cli {
+    /// run without a window, i.e. on a server or in CI
+    #[arg(long)]
+    headless: bool,
+    /// config file to load, defaults to ./ant-sim.toml if it exists
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// number of nests, overrides the config
+    #[arg(long, conflicts_with = "network")]
+    nests: Option<usize>,
+    /// maximum number of ants, overrides the config
+    #[arg(long)]
+    max_ants: Option<u32>,
+    /// seed for the simulation's randomness, overrides the config
+    #[arg(long)]
+    seed: Option<u64>,
+    #[arg(long, value_enum, default_value_t = Mode::Network)]
+    mode: Mode,
+    /// exit after this many simulated seconds
+    #[arg(long, value_name = "SECS")]
+    duration: Option<f32>,
+    /// fixed arena size instead of following the window
+    #[arg(long, value_name = "WIDTHxHEIGHT", value_parser = parse_arena)]
+    arena: Option<BoundingBox>,
+    /// simulation ticks per simulated second
+    #[arg(long, value_name = "HZ")]
+    tick_rate: Option<f32>,
+    /// write samples of the simulation to a .csv or .jsonl file, overrides the config
+    #[arg(long, value_name = "FILE")]
+    export: Option<PathBuf>,
+    /// start from a snapshot saved earlier instead of a fresh network
+    #[arg(long, value_name = "FILE")]
+    load_snapshot: Option<PathBuf>,
+    /// save a snapshot of the network when the simulation exits
+    #[arg(long, value_name = "FILE")]
+    save_snapshot: Option<PathBuf>,
+    /// record the seed, config and every change made while running so the run can be replayed
+    #[arg(long, value_name = "FILE", conflicts_with = "load_snapshot")]
+    record: Option<PathBuf>,
+    /// play back a recorded run tick for tick, its config replaces the config and flags
+    #[arg(long, value_name = "FILE", conflicts_with = "record")]
+    replay: Option<PathBuf>,
+    /// load nests and their neighbors from a .dot, .graphml or .csv edge list instead of
+    /// generating them
+    #[arg(long, value_name = "FILE")]
+    network: Option<PathBuf>,
+    /// csv of nest positions, colors and request rates for a .csv --network
+    #[arg(long, value_name = "FILE", requires = "network")]
+    nodes: Option<PathBuf>,
+    /// write the nests, their pheromones and the traffic along each edge to a .dot file
+    /// when the simulation exits
+    #[arg(long, value_name = "FILE")]
+    export_dot: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Run every combination of parameters in a sweep file headless and write a summary table.
+    /// Other flags set the config each combination starts from.
+    Sweep {
+        /// toml file listing the parameters to compare, see sweep.example.toml
+        file: PathBuf,
+        /// write the summary csv here instead of to stdout
+        #[arg(long, value_name = "FILE")]
+        out: Option<PathBuf>,
+    },
+    /// Search for the decision weights and pheromone rates that do best on an objective,
+    /// starting from the config. Prints the best parameters found as toml.
+    Tune {
+        #[arg(long, value_enum, default_value_t = Objective::MeanHops)]
+        objective: Objective,
+        #[arg(long, default_value_t = 20)]
+        generations: usize,
+        /// candidates per generation
+        #[arg(long, default_value_t = 12)]
+        population: usize,
+        /// seeds every candidate is run with
+        #[arg(long, default_value_t = 3)]
+        runs: u64,
+        /// starting mutation size, as a fraction of each parameter's range
+        #[arg(long, default_value_t = 0.2)]
+        mutation: f32,
+        /// write the best cost of every generation to this csv
+        #[arg(long, value_name = "FILE")]
+        history: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Objective {
+    /// fewest hops per delivered request
+    MeanHops,
+    /// fewest requests failing their hop limit or ttl
+    Failures,
+}
+
+impl From<Objective> for tune::Objective {
+    fn from(objective: Objective) -> Self {
+        match objective {
+            Objective::MeanHops => tune::Objective::MeanHops,
+            Objective::Failures => tune::Objective::Failures,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Mode {
+    Network,
+    Wander,
+}
+
+impl From<Mode> for GameMode {
+    fn from(mode: Mode) -> Self {
+        match mode {
+            Mode::Network => GameMode::AntNetwork,
+            Mode::Wander => GameMode::AntWander,
+        }
+    }
+}
+
 fn main() {
+    let cli = Cli::parse();
+
+    let config = match &cli.config {
+        Some(path) => config::SimConfig::load(path),
+        None => config::SimConfig::load_default(),
+    };
+    let mut config = config.unwrap_or_else(exit_with);
+    // flags take priority over the config file
+    if cli.seed.is_some() {
+        config.seed = cli.seed;
+    }
+    if let Some(nests) = cli.nests {
+        config.network.num_nests = nests;
+    }
+    let mut imported = cli
+        .network
+        .as_ref()
+        .map(|path| ImportedNetwork::load(path, cli.nodes.as_deref()).unwrap_or_else(exit_with));
+    if let Some(network) = &imported {
+        config.network.num_nests = network.nests.len();
+    }
+    if let Some(max_ants) = cli.max_ants {
+        config.network.max_ants = max_ants;
+    }
+    if let Some(path) = cli.export {
+        if let Some(format) = ExportFormat::from_path(&path) {
+            config.export.format = format;
+        }
+        config.export.path = Some(path);
+    }
+    if let Err(e) = config.validate() {
+        exit_with(e);
+    }
+    if let Some(tick_rate) = cli.tick_rate.filter(|&r| !r.is_finite() || r <= 0.) {
+        exit_with(format!("--tick-rate must be positive (got {})", tick_rate));
+    }
+    if let Some(duration) = cli.duration.filter(|&d| !d.is_finite() || d <= 0.) {
+        exit_with(format!("--duration must be positive (got {})", duration));
+    }
+    // the same minimum a window resize has to meet to resize the arena
+    let min_side = 2. * config.network.border_padding;
+    if let Some(arena) = cli.arena.filter(|a| a.w.min(a.h) <= min_side) {
+        exit_with(format!(
+            "--arena must be larger than twice network.border_padding, {} (got {}x{})",
+            min_side, arena.w, arena.h
+        ));
+    }
+    // sweeps and tuning run headless apps with nothing but the built in policies
+    if cli.command.is_some() {
+        if let Err(e) = config.check_policy(&RoutingPolicies::default()) {
+            exit_with(e);
+        }
+    }
+    if cli.command.is_some() && imported.is_some() {
+        exit_with("--network can't be used with sweep or tune yet");
+    }
+    match &cli.command {
+        Some(Command::Sweep { file, out }) => {
+            run_sweep(file, out.as_deref(), &config, cli.duration);
+            return;
+        }
+        Some(Command::Tune {
+            objective,
+            generations,
+            population,
+            runs,
+            mutation,
+            history,
+        }) => {
+            let settings = tune::TuneSettings {
+                objective: (*objective).into(),
+                generations: *generations,
+                population: *population,
+                seeds: (0..(*runs).max(1)).collect(),
+                duration: cli
+                    .duration
+                    .unwrap_or_else(|| exit_with("tuning needs a --duration")),
+                mutation: *mutation,
+                seed: config.seed.unwrap_or_default(),
+            };
+            run_tune(&config, &settings, history.as_deref());
+            return;
+        }
+        None => {}
+    }
+    let recording = cli
+        .replay
+        .as_ref()
+        .map(|path| replay::Recording::load(path).unwrap_or_else(exit_with));
+    if let Some(recording) = &recording {
+        // everything that affects the simulation comes from the recording
+        config = config::SimConfig {
+            export: config.export,
+            ..recording.start.config.clone()
+        };
+        imported = recording.start.network.clone();
+    }
+    if let Mode::Wander = cli.mode {
+        log::warn!("wander mode has no systems yet, nothing will happen");
+    }
+    let headless = cli.headless;
+
     let mut app = App::new();
-    app.add_plugins(DefaultPlugins.set(WindowPlugin {
-        window: WindowDescriptor {
-            title: "Ant sim".to_string(),
-            width: WINDOW_SIZE_X,
-            height: WINDOW_SIZE_Y,
+    if headless {
+        app.add_plugin(headless::HeadlessPlugin::default());
+    } else {
+        app.add_plugins(DefaultPlugins.set(WindowPlugin {
+            window: WindowDescriptor {
+                title: "Ant sim".to_string(),
+                width: WINDOW_SIZE_X,
+                height: WINDOW_SIZE_Y,
+                ..default()
+            },
             ..default()
-        },
-        ..default()
-    }));
-
-    app.add_startup_system(setup_camera)
-        .add_loopless_state(GameMode::AntNetwork)
-        .add_loopless_state(GameState::Play)
-        .init_resource::<HexagonMesh>()
-        .add_plugin(network::AntNetworkPlugin)
+        }))
+        .add_startup_system(setup_camera)
+        .add_system(fit_camera_to_arena)
+        .add_system(arena_follows_window)
         .add_system(toggle_playing)
+        .add_system(clock::time_controls)
+        .add_system(snapshot::snapshot_controls)
+        .add_system(dot::dot_controls)
         .add_plugin(LogDiagnosticsPlugin::default())
         .add_plugin(FrameTimeDiagnosticsPlugin::default())
-        .add_plugin(EntityCountDiagnosticsPlugin::default())
-        // .add_system_set(
-        //     SystemSet::on_update(GameState::Play)
-        //         .with_system(nest::food_request_system)
-        //         .with_system(ant::move_ant_network.label("move ants"))
-        //         .with_system(pheromones::color_and_fade_pheromones.label("color pheromones").after("move ants"))
-        //         .with_system(nest::ant_nest_network_interactions)
-        //         .with_system(nest::fade_nest_network_pheremones)
-        //     )
-        // .add_system_set(
-        //     SystemSet::on_update(GameState::Paused)
-        //         .with_system(ant::animate_ant)
-        //                )
-        // .add_system(ant::ant_wander)
-        // .add_system(pheromones::print_angle)
-        // .add_system(print_camera)
-        .run();
+        .add_plugin(EntityCountDiagnosticsPlugin::default());
+        if recording.is_some() {
+            app.add_system(replay::playback_controls);
+        }
+    }
+
+    if let Some(arena) = cli.arena {
+        // an explicit arena size stays fixed, the camera scales to fit it instead
+        app.insert_resource(arena).insert_resource(ArenaSettings {
+            follow_window: false,
+            relayout_nests: false,
+        });
+    }
+    if let Some(tick_rate) = cli.tick_rate {
+        app.insert_resource(clock::SimClock::from_tick_rate(tick_rate));
+    }
+    if let Some(recording) = &recording {
+        let start = &recording.start;
+        app.insert_resource(start.clock.clone())
+            .insert_resource(start.arena)
+            .insert_resource(ArenaSettings {
+                follow_window: false,
+                ..start.arena_settings.clone()
+            });
+    }
+    if let Some(network) = imported {
+        app.insert_resource(network);
+    }
+    if let Some(path) = &config.export.path {
+        let tick_length = app
+            .world
+            .get_resource_or_insert_with(clock::SimClock::default)
+            .tick_length;
+        let interval_ticks = config.export.interval_ticks(tick_length);
+        let exporter = Exporter::create(path, config.export.format, interval_ticks)
+            .unwrap_or_else(|e| exit_with(format!("couldn't create {}: {}", path.display(), e)));
+        app.insert_resource(exporter);
+    }
+    if let Some(duration) = cli.duration {
+        app.insert_resource(clock::StopAfter(duration))
+            .add_system(clock::stop_after);
+    }
+
+    app.add_loopless_state(GameMode::from(cli.mode))
+        .add_loopless_state(GameState::Play)
+        .init_resource::<HexagonMesh>()
+        .add_plugin(network::AntNetworkPlugin { headless, config });
+    // once every plugin that could register a routing policy has been added
+    let config = config::SimConfig::from_world(&app.world);
+    if let Err(e) = config.check_policy(app.world.resource::<RoutingPolicies>()) {
+        exit_with(e);
+    }
+    // .add_system_set(
+    //     SystemSet::on_update(GameState::Play)
+    //         .with_system(nest::food_request_system)
+    //         .with_system(ant::move_ant_network.label("move ants"))
+    //         .with_system(pheromones::color_and_fade_pheromones.label("color pheromones").after("move ants"))
+    //         .with_system(nest::ant_nest_network_interactions)
+    //         .with_system(nest::fade_nest_network_pheremones)
+    //     )
+    // .add_system_set(
+    //     SystemSet::on_update(GameState::Paused)
+    //         .with_system(ant::animate_ant)
+    //                )
+    // .add_system(ant::ant_wander)
+    // .add_system(pheromones::print_angle)
+    // .add_system(print_camera)
+
+    if let Some(path) = cli.load_snapshot {
+        app.world.send_event(snapshot::LoadSnapshot(path));
+    }
+    if let Some(path) = cli.save_snapshot {
+        app.insert_resource(snapshot::SnapshotOnExit(path))
+            .add_system_to_stage(CoreStage::Last, snapshot::save_on_exit);
+    }
+    if let Some(path) = cli.export_dot {
+        app.insert_resource(dot::DotOnExit(path))
+            .add_system_to_stage(CoreStage::Last, dot::export_dot_on_exit);
+    }
+    // after the network plugin so the recording starts from its resources
+    if let Some(path) = cli.record {
+        let start = replay::RecordingStart::from_world(&app.world);
+        let recorder = replay::Recorder::create(&path, &start)
+            .unwrap_or_else(|e| exit_with(format!("couldn't create {}: {}", path.display(), e)));
+        log::info!(
+            "recording seed {} to {}",
+            start.config.seed.unwrap_or_default(),
+            path.display()
+        );
+        app.insert_resource(recorder)
+            .add_plugin(replay::ReplayPlugin::Record);
+    } else if let Some(recording) = recording {
+        app.add_plugin(replay::ReplayPlugin::Playback(recording));
+    }
+    app.run();
+}
+
+fn run_sweep(file: &Path, out: Option<&Path>, config: &config::SimConfig, duration: Option<f32>) {
+    let sweep = batch::Sweep::load(file).unwrap_or_else(exit_with);
+    let duration = duration.or(sweep.duration).unwrap_or_else(|| {
+        exit_with("a sweep needs a duration, set one in the sweep file or with --duration")
+    });
+    let results = sweep
+        .run(config, duration, |run, total| {
+            eprintln!("run {}/{}", run + 1, total)
+        })
+        .unwrap_or_else(exit_with);
+    let written = match out {
+        Some(path) => File::create(path).and_then(|mut f| results.write_csv(&mut f)),
+        None => results.write_csv(&mut io::stdout().lock()),
+    };
+    if let Err(e) = written {
+        exit_with(e);
+    }
+}
+
+fn run_tune(config: &config::SimConfig, settings: &tune::TuneSettings, history: Option<&Path>) {
+    let result = tune::tune(config, settings, |generation, stats| {
+        eprintln!(
+            "generation {}/{}: best {} mean {}",
+            generation + 1,
+            settings.generations,
+            stats.best_cost,
+            stats.mean_cost
+        )
+    })
+    .unwrap_or_else(exit_with);
+    if let Some(path) = history {
+        if let Err(e) = File::create(path).and_then(|mut f| result.write_history_csv(&mut f)) {
+            exit_with(e);
+        }
+    }
+    eprintln!("best cost: {}", result.best_cost);
+    print!("{}", result.best_toml());
+}
+
+fn exit_with(e: impl Display) -> ! {
+    eprintln!("{}", e);
+    std::process::exit(1);
+}
+
+fn parse_arena(arg: &str) -> Result<BoundingBox, String> {
+    let invalid = || format!("expected <WIDTH>x<HEIGHT>, i.e. 1600x900 (got {})", arg);
+    let (w, h) = arg.split_once('x').ok_or_else(invalid)?;
+    match (w.parse::<f32>(), h.parse::<f32>()) {
+        (Ok(w), Ok(h)) if w.is_finite() && h.is_finite() && w > 0. && h > 0. => {
+            Ok(BoundingBox { w, h })
+        }
+        _ => Err(invalid()),
+    }
 }
 
 fn setup_camera(mut commands: Commands) {
@@ -47,3 +428,19 @@ fn setup_camera(mut commands: Commands) {
     let camera = Camera2dBundle::default();
     commands.spawn(camera);
 }
+
+/// keep the whole arena in view no matter the size or shape of the window
+fn fit_camera_to_arena(
+    bounding_box: Res<BoundingBox>,
+    mut projections: Query<&mut OrthographicProjection, With<Camera2d>>,
+) {
+    if !bounding_box.is_changed() {
+        return;
+    }
+    for mut projection in &mut projections {
+        projection.scaling_mode = ScalingMode::Auto {
+            min_width: bounding_box.w,
+            min_height: bounding_box.h,
+        };
+    }
+}
@@ -3,47 +3,77 @@ use std::{
     f32::consts::{FRAC_PI_2, TAU},
 };
 
-use crate::{ANT_ANIMATION_SPEED, ANT_SCALE, ANT_SPEED, BORDER_PADDING, NUM_NESTS};
+use crate::{clock::SimClock, BoundingBox, NumAnts, SimRng, ANT_ANIMATION_SPEED, ANT_SCALE};
 
 use super::{
-    nest::{Nest, NestColors},
-    DecisionWeights, PheromoneParams,
+    events::{DegenerateWeights, FailureReason, HopFallback, RequestFailed},
+    nest::{Nest, NestId, NestIndex},
+    routing::RoutingPolicies,
+    topology::Topology,
+    DecisionWeights, NetworkParams, PheromoneParams, RequestLimits,
 };
 
 use bevy::{ecs::component::Component, log, prelude::*};
-use rand::{distributions::WeightedIndex, prelude::*, thread_rng, Rng};
+use rand::{
+    distributions::{WeightedError, WeightedIndex},
+    prelude::*,
+    Rng,
+};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Component)]
+#[derive(Debug, Clone, Component, Serialize, Deserialize)]
 pub struct Ant {
-    pub target_color: usize,
-    pub parent_color: usize,
+    pub target: NestId,
+    pub parent: NestId,
     pub carrying_food: bool,
     pub orientation: f32,
     pub target_orientation: f32,
     pub turn_around: bool,
     // pub has_target: bool,
-    pub current_nest: Option<usize>,
-    pub prev_nests: VecDeque<usize>,
+    pub current_nest: Option<NestId>,
+    /// the neighbor the ant is hopping to, flying over any other nest on the way doesn't count
+    #[serde(default)]
+    pub next_nest: Option<NestId>,
+    pub prev_nests: VecDeque<NestId>,
     pub steps: usize,
+    /// nests visited since the request was issued, unlike `steps` this isn't reset at the target
+    pub hops: u32,
+    /// simulated seconds since the request was issued
+    pub age: f32,
+    /// distance travelled since the request was issued
+    pub distance: f32,
+    /// finished and waiting to be pooled, set straight away since [`Pooled`] is only added
+    /// once the tick's commands are applied
+    #[serde(skip)]
+    pub retired: bool,
 }
 
 impl Ant {
-    fn new(target_color: usize, parent_color: usize) -> Self {
-        let mut rng = thread_rng();
+    pub(super) fn new(
+        target: NestId,
+        parent: NestId,
+        num_nests: usize,
+        rng: &mut impl Rng,
+    ) -> Self {
         let angle = rng.gen_range(0.0..TAU);
         // let angle = FRAC_PI_2;
-        let mut prev_nests = VecDeque::with_capacity(NUM_NESTS);
-        prev_nests.push_front(parent_color);
+        let mut prev_nests = VecDeque::with_capacity(num_nests);
+        prev_nests.push_front(parent);
         Self {
-            target_color,
-            parent_color,
+            target,
+            parent,
             carrying_food: false,
             turn_around: false,
             orientation: angle,
             target_orientation: angle,
-            current_nest: Some(parent_color),
+            current_nest: Some(parent),
+            next_nest: None,
             prev_nests,
             steps: 0,
+            hops: 0,
+            age: 0.0,
+            distance: 0.0,
+            retired: false,
         }
     }
 
@@ -66,20 +96,22 @@ impl Ant {
     }
 
     #[inline]
-    pub fn visit_nest(&mut self, color: usize) {
+    pub fn visit_nest(&mut self, nest: NestId) {
         assert!(self.current_nest.is_none());
-        self.prev_nests.push_front(color);
-        self.current_nest = Some(color);
+        self.prev_nests.push_front(nest);
+        self.current_nest = Some(nest);
     }
 
     #[inline]
-    pub fn leave_nest(&mut self) {
-        self.prev_nests.truncate(NUM_NESTS);
+    pub fn leave_nest(&mut self, next: NestId, num_nests: usize) {
+        self.prev_nests.truncate(num_nests);
         self.current_nest = None;
+        self.next_nest = Some(next);
         self.steps += 1;
+        self.hops += 1;
     }
 
-    pub fn prev_nest(&self) -> usize {
+    pub fn prev_nest(&self) -> NestId {
         return *self
             .prev_nests
             .front()
@@ -88,6 +120,17 @@ impl Ant {
 
     pub fn pop_prev_nest(&mut self) {
         self.current_nest = Some(self.prev_nest());
+        self.next_nest = None;
+    }
+
+    /// the nest that issued the request and the nest it was sent to,
+    /// whichever way the ant is currently heading
+    pub fn request(&self) -> (NestId, NestId) {
+        if self.carrying_food {
+            (self.target, self.parent)
+        } else {
+            (self.parent, self.target)
+        }
     }
 
     pub fn wipe_mem(&mut self) {
@@ -108,11 +151,18 @@ const ANT_DROP_VISIBLE_PHEROMONE_SPEED: f32 = 0.2;
 impl AntBundle {
     pub fn new(
         transform: &Transform,
-        target: usize,
-        parent: usize,
+        target: NestId,
+        parent: NestId,
         ant_texture: &Handle<TextureAtlas>,
+        num_nests: usize,
+        rng: &mut impl Rng,
     ) -> Self {
-        let ant = Ant::new(target, parent);
+        let ant = Ant::new(target, parent, num_nests, rng);
+        Self::from_ant(ant, transform, ant_texture)
+    }
+
+    /// an ant already on its way, facing its current orientation
+    pub fn from_ant(ant: Ant, transform: &Transform, ant_texture: &Handle<TextureAtlas>) -> Self {
         let q = Quat::from_rotation_z(ant.orientation - FRAC_PI_2);
         // log::info!(
         //     "Quat {:?} going from {} to {}",
@@ -139,6 +189,69 @@ impl AntBundle {
     }
 }
 
+/// marks ants that finished their request and are waiting in the [`AntPool`]
+#[derive(Debug, Component)]
+pub struct Pooled;
+
+/// Ants that finished their request, hidden and kept around so new requests can reuse
+/// them instead of spawning a new sprite each time.
+#[derive(Debug, Default, Resource, Reflect)]
+#[reflect(Resource)]
+pub struct AntPool {
+    free: Vec<Entity>,
+}
+
+impl AntPool {
+    pub fn len(&self) -> usize {
+        self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.free.is_empty()
+    }
+
+    /// Hides the ant and drops whatever it was carrying until it's reused.
+    /// Returns false if it was already retired this tick, in which case nothing is done.
+    pub fn retire(&mut self, commands: &mut Commands, id: Entity, ant: &mut Ant) -> bool {
+        if ant.retired {
+            return false;
+        }
+        ant.retired = true;
+        commands
+            .entity(id)
+            .despawn_descendants()
+            .insert((Pooled, Visibility { is_visible: false }));
+        self.free.push(id);
+        true
+    }
+
+    /// sends a pooled ant from `transform` towards `target`, or spawns one if none are free
+    #[allow(clippy::too_many_arguments)]
+    pub fn dispatch(
+        &mut self,
+        commands: &mut Commands,
+        transform: &Transform,
+        target: NestId,
+        parent: NestId,
+        ant_texture: &Handle<TextureAtlas>,
+        num_nests: usize,
+        rng: &mut impl Rng,
+    ) -> Entity {
+        let bundle = AntBundle::new(transform, target, parent, ant_texture, num_nests, rng);
+        match self.free.pop() {
+            Some(ant) => {
+                commands.entity(ant).remove::<Pooled>().insert((
+                    bundle.ant,
+                    bundle.sprite_sheet.transform,
+                    Visibility { is_visible: true },
+                ));
+                ant
+            }
+            None => commands.spawn(bundle).id(),
+        }
+    }
+}
+
 #[derive(Component, Deref, DerefMut)]
 pub struct AntAnimationTimer(Timer);
 
@@ -177,18 +290,18 @@ enum Bounds {
 
 // one could argue this is overly verbose and to that I say "and?"
 impl Bounds {
-    fn check(pos: Vec3, logical_bounds: Vec2) -> Option<Self> {
+    fn check(pos: Vec3, logical_bounds: Vec2, border_padding: f32) -> Option<Self> {
         // because 2d is just 3d with z = what should I be on top of in bevy
         let pos: Vec2 = Vec2 { x: pos.x, y: pos.y };
         let diff = logical_bounds - pos.abs();
         let mut collision: Option<Bounds> = None;
-        if diff.x < BORDER_PADDING {
+        if diff.x < border_padding {
             if pos.x < 0.0 {
                 collision = Some(Self::Left);
             } else {
                 collision = Some(Self::Right);
             }
-        } else if diff.y < BORDER_PADDING {
+        } else if diff.y < border_padding {
             if pos.y < 0.0 {
                 collision = Some(Self::Up);
             } else {
@@ -199,86 +312,107 @@ impl Bounds {
     }
 }
 
+/// the distribution to sample a hop to one of `num_candidates` neighbors from,
+/// or why the weights of the routing policy can't be sampled from
+fn hop_distribution(
+    weights: &[f32],
+    num_candidates: usize,
+) -> Result<WeightedIndex<f32>, DegenerateWeights> {
+    let tot: f32 = weights.iter().sum();
+    if num_candidates == 0 {
+        Err(DegenerateWeights::NoCandidates)
+    } else if weights.len() != num_candidates {
+        Err(DegenerateWeights::WrongLength)
+    } else if weights.iter().any(|w| !w.is_finite()) || !tot.is_finite() {
+        // finite weights can still add up to infinity
+        Err(DegenerateWeights::NotFinite)
+    } else if weights.iter().any(|&w| w < 0.0) {
+        Err(DegenerateWeights::Negative)
+    } else if tot <= 0.0 {
+        Err(DegenerateWeights::AllZero)
+    } else {
+        let weights = weights.iter().map(|w| w / tot);
+        WeightedIndex::new(weights).map_err(|e| match e {
+            WeightedError::AllWeightsZero => DegenerateWeights::AllZero,
+            _ => DegenerateWeights::NotFinite,
+        })
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn move_ant(
-    mut ants: Query<(&mut Transform, &mut Ant)>,
-    time: Res<Time>,
+    mut ants: Query<(Entity, &mut Transform, &mut Ant), Without<Pooled>>,
+    clock: Res<SimClock>,
     mut nests: Query<(Entity, &mut Nest)>,
-    nest_ids: Res<NestColors>,
+    nest_ids: Res<NestIndex>,
     decision_weights: Res<DecisionWeights>,
-    windows: Res<Windows>,
+    policies: Res<RoutingPolicies>,
+    topology: Res<Topology>,
+    bounding_box: Res<BoundingBox>,
     pher_params: Res<PheromoneParams>,
+    mut rng: ResMut<SimRng>,
+    params: Res<NetworkParams>,
+    mut fallbacks: EventWriter<HopFallback>,
 ) {
-    let mut rng = thread_rng();
+    let bounds = bounding_box.size();
+    let policy = policies.get_or_default(&decision_weights.policy);
 
-    let win = windows.primary();
-    let bounds = Vec2 {
-        x: win.width(),
-        y: win.height(),
-    };
+    // query order isn't guaranteed, sort so the same seed always gives the same choices
+    let mut ants: Vec<_> = ants.iter_mut().collect();
+    ants.sort_unstable_by_key(|(id, ..)| *id);
 
-    for (mut transform, mut ant) in &mut ants {
+    for (ant_id, mut transform, mut ant) in ants {
+        if ant.retired {
+            continue;
+        }
         let ant_loc = transform.translation.truncate();
-        let bounds_situation = Bounds::check(transform.translation, bounds / 2.0);
+        let bounds_situation =
+            Bounds::check(transform.translation, bounds / 2.0, params.border_padding);
 
         if let Some(_bounds_problem) = bounds_situation {
             //
             ant.pop_prev_nest();
         }
-        if let Some(current_nest_color) = ant.current_nest {
-            let mut weights = vec![0.0; NUM_NESTS];
-            let mut cur_id = None;
-            for (id, nest) in &nests {
-                if nest.color == current_nest_color {
-                    cur_id = Some(id);
-                    continue;
-                }
-                let mut distance_factor = ant_loc.distance(nest.loc);
-
-                let mut pheromone_factor = nest.color_weights[ant.target_color].max(1.0);
-
-                // how recently we visited this nest
-                // TODO:
-                let mut visited_factor = ant
-                    .prev_nests
-                    .iter()
-                    .enumerate()
-                    .filter_map(|(i, &c)| {
-                        if c == nest.color {
-                            // weight by index
-                            Some(1. / (i as f32 + 1.0))
-                        } else {
-                            None
+        if let Some(current_nest) = ant.current_nest {
+            let cur_id = nest_ids[current_nest];
+            let current = nests.get(cur_id).expect("current nest should exist").1;
+            // only neighbors can be hopped to, in id order
+            let candidates: Vec<&Nest> = topology
+                .neighbors(current_nest)
+                .iter()
+                .map(|&id| nests.get(nest_ids[id]).expect("neighbors should exist").1)
+                .collect();
+            let weights = policy.weights(&ant, ant_loc, current, &candidates, &decision_weights);
+            let candidates: Vec<(NestId, Vec2)> =
+                candidates.iter().map(|nest| (nest.id, nest.loc)).collect();
+            let next_nest_id = match hop_distribution(&weights, candidates.len()) {
+                Ok(dist) => candidates[dist.sample(&mut *rng)].0,
+                Err(reason) => {
+                    let next = decision_weights
+                        .fallback
+                        .choose(ant_loc, &candidates, &mut *rng);
+                    fallbacks.send(HopFallback {
+                        ant: ant_id,
+                        nest: current_nest,
+                        next,
+                        reason,
+                        tick: clock.tick,
+                    });
+                    match next {
+                        Some(next) => next,
+                        None => {
+                            // waiting still counts towards the ttl
+                            ant.age += clock.tick_length;
+                            continue;
                         }
-                    })
-                    .sum::<f32>()
-                    // ensure no div by 0
-                    .max(1.0);
-
-                distance_factor = (1.0 / distance_factor).powf(decision_weights.distance_pow);
-                pheromone_factor = pheromone_factor.powf(decision_weights.pheromone_pow);
-                // make it less likely to visit ones we've been too recently
-                visited_factor = (1.0 / visited_factor).powf(decision_weights.visited_pow);
-                // log::info!("factors: dist: {} pher: {} visit: {}", distance_factor, pheromone_factor, visited_factor);
-                let mut factors = vec![distance_factor, pheromone_factor, visited_factor];
-
-                for factor in &mut factors {
-                    if !factor.is_finite() {
-                        *factor = f32::MAX;
                     }
                 }
-                weights[nest.color] = factors.iter().product();
-                weights[current_nest_color] = 0.0;
-            }
-            let tot: f32 = weights.iter().sum();
-            weights = weights.iter().map(|v| v / tot).collect();
-            let dist = WeightedIndex::new(&weights).unwrap();
-            let next_nest_color = dist.sample(&mut rng);
-            if next_nest_color == current_nest_color {
+            };
+            if next_nest_id == current_nest {
                 log::warn!("chose same nest");
                 continue;
             }
-            let next_nest_id = nest_ids.nests[next_nest_color];
-            let next_nest = nests.get(next_nest_id).unwrap().1;
+            let next_nest = nests.get(nest_ids[next_nest_id]).unwrap().1;
             let next_nest_loc: Vec2 = next_nest.loc;
             let curr_trajectory = Vec2::from_angle(ant.orientation);
             let new_trajectory = next_nest_loc - ant_loc;
@@ -290,15 +424,61 @@ pub fn move_ant(
             // ant.set_orientation(new_orientation);
             // ant.set_target_orientation(new_orientation);
 
-            let mut nest_component = nests.get_mut(cur_id.unwrap()).unwrap().1;
+            let mut nest_component = nests.get_mut(cur_id).unwrap().1;
             // leave memory of where we were going and where we came from
-            nest_component.step_pheromone(ant.parent_color, pher_params.nest_step);
-            ant.leave_nest();
+            nest_component.step_pheromone(ant.parent, pher_params.nest_step);
+            ant.leave_nest(next_nest_id, nest_ids.nests.len());
         }
 
-        let delta_time = f32::min(0.2, time.delta_seconds());
-        transform.translation.x += delta_time * ANT_SPEED * ant.orientation.cos();
-        transform.translation.y += delta_time * ANT_SPEED * ant.orientation.sin();
+        ant.age += clock.tick_length;
+        ant.distance += clock.tick_length * params.ant_speed;
+        transform.translation.x += clock.tick_length * params.ant_speed * ant.orientation.cos();
+        transform.translation.y += clock.tick_length * params.ant_speed * ant.orientation.sin();
+    }
+}
+
+/// gives up on requests that went over the hop limit or ttl
+pub fn expire_requests(
+    mut commands: Commands,
+    mut ants: Query<(Entity, &mut Ant), Without<Pooled>>,
+    limits: Res<RequestLimits>,
+    clock: Res<SimClock>,
+    mut pool: ResMut<AntPool>,
+    mut num_ants: ResMut<NumAnts>,
+    mut failed: EventWriter<RequestFailed>,
+) {
+    // retired in entity order so the pool, and which ant is reused next, is reproducible
+    let mut ants: Vec<_> = ants.iter_mut().collect();
+    ants.sort_unstable_by_key(|(id, _)| *id);
+
+    for (id, mut ant) in ants {
+        // delivered earlier in the tick
+        if ant.retired {
+            continue;
+        }
+        let reason = if limits.max_hops > 0 && ant.hops > limits.max_hops {
+            FailureReason::HopLimit
+        } else if limits.ttl > 0.0 && ant.age > limits.ttl {
+            FailureReason::Ttl
+        } else {
+            continue;
+        };
+        if !pool.retire(&mut commands, id, &mut ant) {
+            continue;
+        }
+        let (origin, target) = ant.request();
+        failed.send(RequestFailed {
+            ant: id,
+            origin,
+            target,
+            reached_target: ant.carrying_food,
+            reason,
+            hops: ant.hops,
+            distance: ant.distance,
+            tick: clock.tick,
+            elapsed: ant.age,
+        });
+        num_ants.0 = num_ants.0.saturating_sub(1);
     }
 }
 
@@ -313,3 +493,52 @@ pub fn load_ant_texture(
     let texture_atlas_handle = texture_atlases.add(texture_atlas);
     commands.insert_resource(AntTexture(texture_atlas_handle));
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn degenerate(weights: &[f32], num_candidates: usize) -> Option<DegenerateWeights> {
+        hop_distribution(weights, num_candidates).err()
+    }
+
+    #[test]
+    fn degenerate_weights_fall_back() {
+        assert_eq!(degenerate(&[], 0), Some(DegenerateWeights::NoCandidates));
+        assert_eq!(degenerate(&[1.], 2), Some(DegenerateWeights::WrongLength));
+        assert_eq!(
+            degenerate(&[1., 2., 3.], 2),
+            Some(DegenerateWeights::WrongLength)
+        );
+        assert_eq!(
+            degenerate(&[1., f32::NAN], 2),
+            Some(DegenerateWeights::NotFinite)
+        );
+        assert_eq!(
+            degenerate(&[1., f32::INFINITY], 2),
+            Some(DegenerateWeights::NotFinite)
+        );
+        assert_eq!(
+            degenerate(&[f32::MAX, f32::MAX], 2),
+            Some(DegenerateWeights::NotFinite)
+        );
+        assert_eq!(degenerate(&[0., 0.], 2), Some(DegenerateWeights::AllZero));
+        assert_eq!(
+            degenerate(&[1., -0.5], 2),
+            Some(DegenerateWeights::Negative)
+        );
+        assert_eq!(
+            degenerate(&[-1., -1.], 2),
+            Some(DegenerateWeights::Negative)
+        );
+    }
+
+    #[test]
+    fn sampled_hops_follow_the_weights() {
+        assert_eq!(degenerate(&[0., 2.], 2), None);
+        assert_eq!(degenerate(&[-0., 1.], 2), None);
+        let dist = hop_distribution(&[0., 1., 0.], 3).unwrap();
+        let mut rng = SimRng::seed_from_u64(1);
+        assert!((0..100).all(|_| dist.sample(&mut rng) == 1));
+    }
+}
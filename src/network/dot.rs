@@ -0,0 +1,261 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use bevy::{app::AppExit, ecs::system::SystemParam, log, prelude::*};
+
+use crate::{clock::SimClock, Colors};
+
+use super::{
+    import::ImportedNetwork,
+    nest::{Nest, NestId, NestIndex},
+    topology::Topology,
+};
+
+/// where `G` writes the network to
+pub const DEFAULT_DOT_PATH: &str = "ant-sim-network.dot";
+
+/// thickest an edge is drawn, for the one with the most traffic
+const MAX_PENWIDTH: f32 = 8.;
+
+/// hops ants have made along each edge, in either direction
+#[derive(Debug, Default, Clone, Resource)]
+pub struct EdgeTraffic {
+    hops: BTreeMap<(NestId, NestId), u64>,
+}
+
+impl EdgeTraffic {
+    fn key(a: NestId, b: NestId) -> (NestId, NestId) {
+        (a.min(b), a.max(b))
+    }
+
+    /// an ant hopped from `from` to `to`
+    pub fn record(&mut self, from: NestId, to: NestId) {
+        *self.hops.entry(Self::key(from, to)).or_default() += 1;
+    }
+
+    pub fn get(&self, a: NestId, b: NestId) -> u64 {
+        self.hops.get(&Self::key(a, b)).copied().unwrap_or_default()
+    }
+
+    pub fn max(&self) -> u64 {
+        self.hops.values().copied().max().unwrap_or_default()
+    }
+}
+
+/// writes the network to the file
+#[derive(Debug, Clone)]
+pub struct ExportDot(pub PathBuf);
+
+/// where to write the network when the app exits
+#[derive(Debug, Clone, Resource)]
+pub struct DotOnExit(pub PathBuf);
+
+/// graphviz strings only escape quotes
+fn quoted(id: &str) -> String {
+    format!("\"{}\"", id.replace('"', "\\\""))
+}
+
+fn hex(color: Color) -> String {
+    let [r, g, b, _] = color
+        .as_rgba_f32()
+        .map(|c| (c.clamp(0., 1.) * 255.).round() as u8);
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+/// Everything that goes into the DOT file. Nests keep the names they were imported with,
+/// so an exported network can be imported again with `--network`.
+#[derive(SystemParam)]
+pub struct NetworkGraph<'w, 's> {
+    nest_ids: Res<'w, NestIndex>,
+    nests: Query<'w, 's, &'static Nest>,
+    colors: Res<'w, Colors>,
+    topology: Res<'w, Topology>,
+    traffic: Res<'w, EdgeTraffic>,
+    clock: Res<'w, SimClock>,
+    imported: Option<Res<'w, ImportedNetwork>>,
+}
+
+impl NetworkGraph<'_, '_> {
+    fn name(&self, nest: NestId) -> String {
+        match self.imported.as_ref().and_then(|n| n.nests.get(nest.0)) {
+            Some(imported) => quoted(&imported.name),
+            None => quoted(&nest.to_string()),
+        }
+    }
+
+    /// Nests with their position, color and pheromone strength towards each nest by id,
+    /// and every edge of the topology weighted by how many ants hopped along it.
+    pub fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "graph network {{")?;
+        writeln!(
+            out,
+            "  // tick {}, {} simulated seconds",
+            self.clock.tick,
+            self.clock.elapsed_seconds()
+        )?;
+        writeln!(out, "  node [shape=circle, style=filled];")?;
+        for &entity in &self.nest_ids.nests {
+            let nest = self
+                .nests
+                .get(entity)
+                .expect("nest index should only contain existing nests");
+            let weights: Vec<String> = nest.weights.iter().map(|w| format!("{:.4}", w)).collect();
+            write!(
+                out,
+                "  {} [pos=\"{},{}!\", fillcolor=\"{}\", weights=\"{}\"",
+                self.name(nest.id),
+                nest.loc.x,
+                nest.loc.y,
+                hex(self.colors.colors[self.colors.color_of(nest.id)]),
+                weights.join(",")
+            )?;
+            if let Some(rate) = nest.request_prob {
+                write!(out, ", rate={}", rate)?;
+            }
+            writeln!(out, "];")?;
+        }
+        let max = self.traffic.max().max(1) as f32;
+        for (a, b) in self.topology.edges() {
+            let traffic = self.traffic.get(a, b);
+            writeln!(
+                out,
+                "  {} -- {} [traffic={}, weight={}, penwidth={:.2}];",
+                self.name(a),
+                self.name(b),
+                traffic,
+                traffic,
+                1. + (MAX_PENWIDTH - 1.) * traffic as f32 / max
+            )?;
+        }
+        writeln!(out, "}}")
+    }
+
+    pub fn save(&self, path: &Path) {
+        let written = File::create(path).and_then(|f| {
+            let mut out = BufWriter::new(f);
+            self.write(&mut out)?;
+            out.flush()
+        });
+        match written {
+            Ok(()) => log::info!("wrote network to {}", path.display()),
+            Err(e) => log::error!("couldn't write {}: {}", path.display(), e),
+        }
+    }
+}
+
+pub fn export_dot(mut exports: EventReader<ExportDot>, graph: NetworkGraph) {
+    for ExportDot(path) in exports.iter() {
+        graph.save(path);
+    }
+}
+
+pub fn export_dot_on_exit(exit: EventReader<AppExit>, path: Res<DotOnExit>, graph: NetworkGraph) {
+    if !exit.is_empty() {
+        graph.save(&path.0);
+    }
+}
+
+/// `G` to write the network to [`DEFAULT_DOT_PATH`]
+pub fn dot_controls(keys: Res<Input<KeyCode>>, mut exports: EventWriter<ExportDot>) {
+    if keys.just_pressed(KeyCode::G) {
+        exports.send(ExportDot(DEFAULT_DOT_PATH.into()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::SystemState;
+
+    use super::{super::import::ImportedNest, *};
+
+    /// the nests of `network`, at `locs`, with edges between consecutive nests
+    fn world(network: &ImportedNetwork, locs: &[Vec2]) -> World {
+        let num_nests = network.nests.len();
+        let mut world = World::new();
+        let nests = network
+            .nests
+            .iter()
+            .zip(locs)
+            .enumerate()
+            .map(|(i, (imported, &loc))| {
+                let mut nest = Nest::new(NestId(i), loc, num_nests);
+                nest.request_prob = imported.request_prob;
+                world.spawn(nest).id()
+            })
+            .collect();
+        let colors = network
+            .nests
+            .iter()
+            .map(|n| n.color.unwrap_or(Color::WHITE))
+            .collect();
+        world.insert_resource(NestIndex { nests });
+        world.insert_resource(Colors {
+            colors,
+            color_handles: vec![Handle::default(); num_nests],
+            nest_colors: (0..num_nests).collect(),
+        });
+        let mut topology = Topology::empty(num_nests);
+        let mut traffic = EdgeTraffic::default();
+        for i in 1..num_nests {
+            topology.add_edge(NestId(i - 1), NestId(i));
+            traffic.record(NestId(i), NestId(i - 1));
+        }
+        world.insert_resource(topology);
+        world.insert_resource(traffic);
+        world.insert_resource(SimClock::default());
+        world.insert_resource(network.clone());
+        world
+    }
+
+    fn write(world: &mut World) -> String {
+        let mut state = SystemState::<NetworkGraph>::new(world);
+        let mut out = Vec::new();
+        state.get_mut(world).write(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn nest(name: &str, color: &str, request_prob: Option<f32>) -> ImportedNest {
+        ImportedNest {
+            name: name.to_string(),
+            x: None,
+            y: None,
+            color: Some(Color::hex(color).unwrap()),
+            request_prob,
+        }
+    }
+
+    #[test]
+    fn written_network_can_be_imported_again() {
+        let network = ImportedNetwork {
+            nests: vec![
+                nest("say \"hi\"", "ff8800", Some(0.02)),
+                nest("b", "0088ff", None),
+                nest("c c", "ffffff", Some(1.)),
+            ],
+            edges: vec![],
+        };
+        let locs = [
+            Vec2::new(0., 0.),
+            Vec2::new(100., 50.),
+            Vec2::new(-25.5, 10.),
+        ];
+        let dot = write(&mut world(&network, &locs));
+
+        let imported = ImportedNetwork::from_dot(&dot).unwrap();
+        assert_eq!(imported.nests.len(), 3);
+        for ((imported, original), loc) in imported.nests.iter().zip(&network.nests).zip(locs) {
+            assert_eq!(imported.name, original.name);
+            assert_eq!(imported.loc(), Some(loc));
+            assert_eq!(imported.color, original.color);
+            assert_eq!(imported.request_prob, original.request_prob);
+        }
+        assert_eq!(
+            imported.edges,
+            [(NestId(0), NestId(1)), (NestId(1), NestId(2))]
+        );
+    }
+}
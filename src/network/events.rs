@@ -0,0 +1,180 @@
+use std::fmt;
+
+use bevy::{log, prelude::*};
+
+use super::nest::NestId;
+
+// every event carries the tick it happened on and, for events about a request in
+// progress, how many hops, how far and how many simulated seconds the ant has
+// travelled since it was issued
+
+/// a nest sent an ant to fetch data from `target`
+#[derive(Debug, Clone)]
+pub struct RequestIssued {
+    pub ant: Entity,
+    pub origin: NestId,
+    pub target: NestId,
+    pub tick: u64,
+}
+
+/// an ant passed through a nest that wasn't the one it was heading to
+#[derive(Debug, Clone)]
+pub struct AntVisitedNest {
+    pub ant: Entity,
+    pub nest: NestId,
+    /// the nest the ant came from
+    pub from: NestId,
+    pub origin: NestId,
+    pub target: NestId,
+    pub hops: u32,
+    pub tick: u64,
+    pub elapsed: f32,
+}
+
+/// an ant found the data at `target` and is heading back to `origin`
+#[derive(Debug, Clone)]
+pub struct TargetReached {
+    pub ant: Entity,
+    pub origin: NestId,
+    pub target: NestId,
+    pub hops: u32,
+    pub distance: f32,
+    pub tick: u64,
+    pub elapsed: f32,
+}
+
+/// an ant made it back to `origin` with the data, the request succeeded
+#[derive(Debug, Clone)]
+pub struct DeliveryCompleted {
+    pub ant: Entity,
+    pub origin: NestId,
+    pub target: NestId,
+    pub hops: u32,
+    pub distance: f32,
+    pub tick: u64,
+    pub elapsed: f32,
+}
+
+/// an ant gave up before making it back to `origin`
+#[derive(Debug, Clone)]
+pub struct RequestFailed {
+    pub ant: Entity,
+    pub origin: NestId,
+    pub target: NestId,
+    /// whether the ant had already found the target
+    pub reached_target: bool,
+    pub reason: FailureReason,
+    pub hops: u32,
+    pub distance: f32,
+    pub tick: u64,
+    pub elapsed: f32,
+}
+
+/// the decision weights of an ant's next hop couldn't be sampled from, so the
+/// [`super::FallbackPolicy`] chose `next` instead, `None` if the ant stays where it is
+#[derive(Debug, Clone)]
+pub struct HopFallback {
+    pub ant: Entity,
+    pub nest: NestId,
+    pub next: Option<NestId>,
+    pub reason: DegenerateWeights,
+    pub tick: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DegenerateWeights {
+    /// the nest has no neighbors to go to
+    NoCandidates,
+    /// the routing policy didn't give one weight per neighbor
+    WrongLength,
+    AllZero,
+    /// any weight below zero
+    Negative,
+    /// infinite or NaN, or adding up to infinity, i.e. from factors overflowing
+    NotFinite,
+}
+
+impl fmt::Display for DegenerateWeights {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DegenerateWeights::NoCandidates => write!(f, "no neighbors"),
+            DegenerateWeights::WrongLength => write!(f, "not one weight per neighbor"),
+            DegenerateWeights::AllZero => write!(f, "all weights zero"),
+            DegenerateWeights::Negative => write!(f, "negative weights"),
+            DegenerateWeights::NotFinite => write!(f, "weights not finite"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureReason {
+    HopLimit,
+    Ttl,
+}
+
+impl fmt::Display for FailureReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FailureReason::HopLimit => write!(f, "hop limit"),
+            FailureReason::Ttl => write!(f, "ttl"),
+        }
+    }
+}
+
+pub fn log_events(
+    mut issued: EventReader<RequestIssued>,
+    mut visited: EventReader<AntVisitedNest>,
+    mut reached: EventReader<TargetReached>,
+    mut completed: EventReader<DeliveryCompleted>,
+    mut failed: EventReader<RequestFailed>,
+    mut fallbacks: EventReader<HopFallback>,
+) {
+    for e in issued.iter() {
+        log::debug!("generated ant: nest {} target: {}", e.origin, e.target);
+    }
+    for e in visited.iter() {
+        log::trace!(
+            "ant heading to {} taking pit stop at {} after {} hops",
+            e.target,
+            e.nest,
+            e.hops
+        );
+    }
+    for e in reached.iter() {
+        log::debug!(
+            "Ant reached target nest {} after {} hops and {:.1}s",
+            e.target,
+            e.hops,
+            e.elapsed
+        );
+    }
+    for e in completed.iter() {
+        log::debug!(
+            "Ant reached parent nest {} after {} hops and {:.1}s",
+            e.origin,
+            e.hops,
+            e.elapsed
+        );
+    }
+    for e in failed.iter() {
+        log::debug!(
+            "request from nest {} to {} failed after {} hops and {:.1}s: exceeded {}",
+            e.origin,
+            e.target,
+            e.hops,
+            e.elapsed,
+            e.reason
+        );
+    }
+    for e in fallbacks.iter() {
+        match e.next {
+            Some(next) => log::warn!(
+                "ant at nest {} fell back to nest {}: {}",
+                e.nest,
+                next,
+                e.reason
+            ),
+            None => log::warn!("ant at nest {} has nowhere to go: {}", e.nest, e.reason),
+        }
+    }
+}
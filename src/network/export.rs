@@ -0,0 +1,293 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use bevy::{app::AppExit, log, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{clock::SimClock, NumAnts};
+
+use super::{metrics::Metrics, nest::Nest, pheromones::PheromoneManager, RequestStats};
+
+const EXPORT_INTERVAL: f32 = 1.;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    /// one json object per line
+    Jsonl,
+}
+
+impl ExportFormat {
+    /// the format matching a file's extension, if there is one
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "csv" => Some(ExportFormat::Csv),
+            "jsonl" | "ndjson" => Some(ExportFormat::Jsonl),
+            _ => None,
+        }
+    }
+}
+
+/// where and how often samples of the simulation are written, nothing is written without a path
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExportSettings {
+    pub path: Option<PathBuf>,
+    pub format: ExportFormat,
+    /// simulated seconds between samples
+    pub interval: f32,
+}
+
+impl ExportSettings {
+    /// ticks between samples, at least one so an interval shorter than a tick samples every tick
+    pub fn interval_ticks(&self, tick_length: f32) -> u64 {
+        ((self.interval / tick_length).round() as u64).max(1)
+    }
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        Self {
+            path: None,
+            format: ExportFormat::default(),
+            interval: EXPORT_INTERVAL,
+        }
+    }
+}
+
+/// the state of the simulation at the end of a tick
+#[derive(Debug, Clone, Serialize)]
+pub struct Sample {
+    pub tick: u64,
+    /// simulated seconds
+    pub time: f32,
+    pub active_ants: u32,
+    pub delivered: u64,
+    pub failed: u64,
+    /// over the most recent delivered requests, see [`super::metrics::ROLLING_WINDOW`]
+    pub mean_hops: Option<f32>,
+    pub pheromone_cells: usize,
+    /// fraction of the pheromone grid with a pheromone in it
+    pub pheromone_occupancy: f32,
+    /// total pheromone strength held by all nests
+    pub nest_pheromone: f32,
+}
+
+const CSV_HEADER: &str = "tick,time,active_ants,delivered,failed,mean_hops,pheromone_cells,pheromone_occupancy,nest_pheromone";
+
+impl Sample {
+    fn write_csv(&self, out: &mut impl Write) -> io::Result<()> {
+        let mean_hops = self.mean_hops.map(|h| h.to_string()).unwrap_or_default();
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{},{}",
+            self.tick,
+            self.time,
+            self.active_ants,
+            self.delivered,
+            self.failed,
+            mean_hops,
+            self.pheromone_cells,
+            self.pheromone_occupancy,
+            self.nest_pheromone
+        )
+    }
+
+    fn write_jsonl(&self, out: &mut impl Write) -> io::Result<()> {
+        serde_json::to_writer(&mut *out, self)?;
+        writeln!(out)
+    }
+}
+
+/// writes a [`Sample`] every `interval` simulated seconds
+#[derive(Resource)]
+pub struct Exporter {
+    out: BufWriter<File>,
+    format: ExportFormat,
+    interval_ticks: u64,
+}
+
+impl Exporter {
+    pub fn create(path: &Path, format: ExportFormat, interval_ticks: u64) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        if format == ExportFormat::Csv {
+            writeln!(out, "{}", CSV_HEADER)?;
+        }
+        Ok(Self {
+            out,
+            format,
+            interval_ticks: interval_ticks.max(1),
+        })
+    }
+
+    pub fn write(&mut self, sample: &Sample) -> io::Result<()> {
+        match self.format {
+            ExportFormat::Csv => sample.write_csv(&mut self.out),
+            ExportFormat::Jsonl => sample.write_jsonl(&mut self.out),
+        }
+    }
+}
+
+pub fn export_samples(
+    mut exporter: ResMut<Exporter>,
+    clock: Res<SimClock>,
+    num_ants: Res<NumAnts>,
+    stats: Res<RequestStats>,
+    metrics: Res<Metrics>,
+    pheromone_manager: Query<&PheromoneManager>,
+    nests: Query<&Nest>,
+) {
+    if clock.tick % exporter.interval_ticks != 0 {
+        return;
+    }
+    let pheromone_manager = pheromone_manager
+        .get_single()
+        .expect("there should be pheromones");
+    let pheromone_cells = pheromone_manager.filled_cells();
+    let sample = Sample {
+        tick: clock.tick,
+        time: clock.elapsed_seconds(),
+        active_ants: num_ants.0,
+        delivered: stats.completed,
+        failed: stats.failed(),
+        mean_hops: metrics.rolling.hops.mean(),
+        pheromone_cells,
+        pheromone_occupancy: pheromone_cells as f32 / pheromone_manager.num_cells().max(1) as f32,
+        nest_pheromone: nests.iter().flat_map(|n| &n.weights).sum(),
+    };
+    if let Err(e) = exporter.write(&sample) {
+        log::error!("couldn't write sample: {}", e);
+    }
+}
+
+/// the app may exit without dropping its resources, so flush explicitly
+pub fn flush_on_exit(exit: EventReader<AppExit>, mut exporter: ResMut<Exporter>) {
+    if !exit.is_empty() {
+        if let Err(e) = exporter.out.flush() {
+            log::error!("couldn't write samples: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn samples() -> [Sample; 2] {
+        [
+            Sample {
+                tick: 60,
+                time: 1.,
+                active_ants: 3,
+                delivered: 0,
+                failed: 0,
+                mean_hops: None,
+                pheromone_cells: 10,
+                pheromone_occupancy: 0.25,
+                nest_pheromone: 1.5,
+            },
+            Sample {
+                tick: 120,
+                time: 2.,
+                active_ants: 5,
+                delivered: 4,
+                failed: 1,
+                mean_hops: Some(2.5),
+                pheromone_cells: 20,
+                pheromone_occupancy: 0.5,
+                nest_pheromone: 3.,
+            },
+        ]
+    }
+
+    fn export(format: ExportFormat) -> String {
+        let path = std::env::temp_dir().join(format!(
+            "ant-sim-export-test-{}-{:?}",
+            std::process::id(),
+            format
+        ));
+        let mut exporter = Exporter::create(&path, format, 1).unwrap();
+        for sample in &samples() {
+            exporter.write(sample).unwrap();
+        }
+        exporter.out.flush().unwrap();
+        drop(exporter);
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        contents
+    }
+
+    #[test]
+    fn csv_has_a_header_and_a_row_per_sample() {
+        let contents = export(ExportFormat::Csv);
+        let lines: Vec<_> = contents.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                CSV_HEADER,
+                "60,1,3,0,0,,10,0.25,1.5",
+                "120,2,5,4,1,2.5,20,0.5,3"
+            ]
+        );
+        let columns = CSV_HEADER.split(',').count();
+        assert!(lines.iter().all(|line| line.split(',').count() == columns));
+    }
+
+    #[test]
+    fn jsonl_has_an_object_per_sample() {
+        let contents = export(ExportFormat::Jsonl);
+        let rows: Vec<serde_json::Value> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["tick"], 60);
+        assert!(rows[0]["mean_hops"].is_null());
+        assert_eq!(rows[1]["delivered"], 4);
+        assert_eq!(rows[1]["mean_hops"], 2.5);
+        assert_eq!(rows[1]["pheromone_occupancy"], 0.5);
+        let fields = CSV_HEADER.split(',').collect::<Vec<_>>();
+        for row in &rows {
+            let keys = row.as_object().unwrap().keys().map(String::as_str);
+            let mut keys = keys.collect::<Vec<_>>();
+            let mut expected = fields.clone();
+            keys.sort_unstable();
+            expected.sort_unstable();
+            assert_eq!(keys, expected);
+        }
+    }
+
+    #[test]
+    fn interval_is_rounded_to_at_least_a_tick() {
+        let tick_length = 1. / 60.;
+        let ticks = |interval| {
+            ExportSettings {
+                interval,
+                ..ExportSettings::default()
+            }
+            .interval_ticks(tick_length)
+        };
+        assert_eq!(ticks(1.), 60);
+        assert_eq!(ticks(0.5), 30);
+        assert_eq!(ticks(1.01), 61);
+        assert_eq!(ticks(tick_length), 1);
+        assert_eq!(ticks(0.001), 1);
+    }
+
+    #[test]
+    fn format_follows_the_extension() {
+        let format = |path: &str| ExportFormat::from_path(Path::new(path));
+        assert_eq!(format("samples.csv"), Some(ExportFormat::Csv));
+        assert_eq!(format("samples.jsonl"), Some(ExportFormat::Jsonl));
+        assert_eq!(format("samples.ndjson"), Some(ExportFormat::Jsonl));
+        assert_eq!(format("samples.txt"), None);
+        assert_eq!(format("samples"), None);
+    }
+}
@@ -1,12 +1,15 @@
 use bevy::ecs::component::Component;
 
+use super::nest::NestId;
+
 #[derive(Debug, Clone, Copy, Component)]
 pub struct Food {
-    pub color: usize,
+    /// the nest the food was picked up from
+    pub nest: NestId,
 }
 
 impl Food {
-    pub fn new(color: usize) -> Self {
-        Self { color }
+    pub fn new(nest: NestId) -> Self {
+        Self { nest }
     }
 }
@@ -0,0 +1,805 @@
+use std::{collections::BTreeMap, fmt, fs, io, path::Path};
+
+use bevy::prelude::*;
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::BoundingBox;
+
+use super::{nest::NestId, topology::Topology};
+
+/// a node of an imported network, any attribute it didn't have is left out
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImportedNest {
+    /// its id in the file
+    pub name: String,
+    pub x: Option<f32>,
+    pub y: Option<f32>,
+    pub color: Option<Color>,
+    /// chance of requesting data every tick, overrides `network.food_request_prob`
+    pub request_prob: Option<f32>,
+}
+
+impl ImportedNest {
+    pub fn loc(&self) -> Option<Vec2> {
+        Some(Vec2::new(self.x?, self.y?))
+    }
+}
+
+/// Nests and edges loaded from a file, used instead of generating them.
+/// Nests get their ids in the order they first appear in the file.
+/// Edges go both ways, even when the file is a directed graph.
+#[derive(Debug, Clone, PartialEq, Resource, Serialize, Deserialize)]
+pub struct ImportedNetwork {
+    pub nests: Vec<ImportedNest>,
+    pub edges: Vec<(NestId, NestId)>,
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    Io(io::Error),
+    Xml(quick_xml::Error),
+    Syntax {
+        line: usize,
+        message: String,
+    },
+    /// one message per problem
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::Io(e) => write!(f, "couldn't read network: {}", e),
+            ImportError::Xml(e) => write!(f, "couldn't parse network: {}", e),
+            ImportError::Syntax { line, message } => {
+                write!(f, "couldn't parse line {} of network: {}", line, message)
+            }
+            ImportError::Invalid(problems) => {
+                write!(f, "invalid network:")?;
+                for problem in problems {
+                    write!(f, "\n  {}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+impl From<io::Error> for ImportError {
+    fn from(e: io::Error) -> Self {
+        ImportError::Io(e)
+    }
+}
+
+impl From<quick_xml::Error> for ImportError {
+    fn from(e: quick_xml::Error) -> Self {
+        ImportError::Xml(e)
+    }
+}
+
+fn syntax(line: usize, message: impl Into<String>) -> ImportError {
+    ImportError::Syntax {
+        line,
+        message: message.into(),
+    }
+}
+
+/// collects nests by name and edges between them while a file is parsed
+#[derive(Default)]
+struct Builder {
+    nests: Vec<ImportedNest>,
+    ids: BTreeMap<String, NestId>,
+    edges: Vec<(NestId, NestId)>,
+    problems: Vec<String>,
+}
+
+impl Builder {
+    fn nest(&mut self, name: &str) -> NestId {
+        if let Some(&id) = self.ids.get(name) {
+            return id;
+        }
+        let id = NestId(self.nests.len());
+        self.nests.push(ImportedNest {
+            name: name.to_string(),
+            x: None,
+            y: None,
+            color: None,
+            request_prob: None,
+        });
+        self.ids.insert(name.to_string(), id);
+        id
+    }
+
+    fn edge(&mut self, a: &str, b: &str) {
+        let edge = (self.nest(a), self.nest(b));
+        self.edges.push(edge);
+    }
+
+    /// sets a known attribute, others are ignored
+    fn attr(&mut self, id: NestId, key: &str, value: &str) {
+        let value = value.trim();
+        let nest = &mut self.nests[id.0];
+        let expected = match key {
+            // graphviz positions can end in `!` to pin them
+            "pos" => {
+                let coords = value.trim_end_matches('!').split_once(',');
+                match coords.map(|(x, y)| (x.trim().parse(), y.trim().parse())) {
+                    Some((Ok(x), Ok(y))) => {
+                        nest.x = Some(x);
+                        nest.y = Some(y);
+                        return;
+                    }
+                    _ => "x,y",
+                }
+            }
+            "x" | "y" => match value.parse() {
+                Ok(x) if key == "x" => {
+                    nest.x = Some(x);
+                    return;
+                }
+                Ok(y) => {
+                    nest.y = Some(y);
+                    return;
+                }
+                Err(_) => "a number",
+            },
+            "color" | "fillcolor" => match Color::hex(value.trim_start_matches('#')) {
+                Ok(color) => {
+                    nest.color = Some(color);
+                    return;
+                }
+                Err(_) => "a hex color like #ff8800",
+            },
+            "rate" => match value.parse::<f32>() {
+                Ok(rate) if (0.0..=1.0).contains(&rate) => {
+                    nest.request_prob = Some(rate);
+                    return;
+                }
+                _ => "between 0 and 1",
+            },
+            _ => return,
+        };
+        self.problems.push(format!(
+            "nest {}: {} should be {} (got {:?})",
+            nest.name, key, expected, value
+        ));
+    }
+
+    fn finish(self) -> Result<ImportedNetwork, ImportError> {
+        let mut problems = self.problems;
+        if self.nests.is_empty() {
+            problems.push("there are no nests".to_string());
+        }
+        let positioned = self.nests.iter().filter(|n| n.loc().is_some()).count();
+        if positioned != 0 && positioned != self.nests.len() {
+            problems.push(format!(
+                "{} of {} nests have a position, either all or none should",
+                positioned,
+                self.nests.len()
+            ));
+        }
+        if !problems.is_empty() {
+            return Err(ImportError::Invalid(problems));
+        }
+        Ok(ImportedNetwork {
+            nests: self.nests,
+            edges: self.edges,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Id(String),
+    Symbol(&'static str),
+}
+
+/// graphviz tokens and the line each is on, comments left out
+fn dot_tokens(contents: &str) -> Result<Vec<(usize, Token)>, ImportError> {
+    let mut tokens = Vec::new();
+    let mut chars = contents.chars().peekable();
+    let mut line = 1;
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => {}
+            '#' => {
+                while chars.peek().map_or(false, |&c| c != '\n') {
+                    chars.next();
+                }
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.peek().map_or(false, |&c| c != '\n') {
+                    chars.next();
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if last == '*' => break,
+                        Some(c) => {
+                            line += (c == '\n') as usize;
+                            last = c;
+                        }
+                        None => return Err(syntax(line, "comment isn't closed")),
+                    }
+                }
+            }
+            '{' | '}' | '[' | ']' | ';' | ',' | '=' | ':' => {
+                let symbol = match c {
+                    '{' => "{",
+                    '}' => "}",
+                    '[' => "[",
+                    ']' => "]",
+                    ';' => ";",
+                    ',' => ",",
+                    '=' => "=",
+                    _ => ":",
+                };
+                tokens.push((line, Token::Symbol(symbol)));
+            }
+            '-' if matches!(chars.peek(), Some('-') | Some('>')) => {
+                chars.next();
+                tokens.push((line, Token::Symbol("--")));
+            }
+            '"' => {
+                let start = line;
+                let mut id = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') if chars.peek() == Some(&'"') => id.push(chars.next().unwrap()),
+                        // a line continuation
+                        Some('\\') if chars.peek() == Some(&'\n') => {
+                            chars.next();
+                            line += 1;
+                        }
+                        Some(c) => {
+                            line += (c == '\n') as usize;
+                            id.push(c);
+                        }
+                        None => return Err(syntax(start, "string isn't closed")),
+                    }
+                }
+                tokens.push((start, Token::Id(id)));
+            }
+            c if c.is_alphanumeric() || matches!(c, '_' | '.' | '-') => {
+                let mut id = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_alphanumeric() || matches!(c, '_' | '.')) {
+                        break;
+                    }
+                    id.push(c);
+                    chars.next();
+                }
+                tokens.push((line, Token::Id(id)));
+            }
+            '<' => return Err(syntax(line, "html labels aren't supported")),
+            c => return Err(syntax(line, format!("unexpected {:?}", c))),
+        }
+    }
+    Ok(tokens)
+}
+
+struct DotParser {
+    tokens: std::iter::Peekable<std::vec::IntoIter<(usize, Token)>>,
+    line: usize,
+}
+
+impl DotParser {
+    fn next(&mut self) -> Option<Token> {
+        let (line, token) = self.tokens.next()?;
+        self.line = line;
+        Some(token)
+    }
+
+    fn peek(&mut self) -> Option<&Token> {
+        self.tokens.peek().map(|(_, token)| token)
+    }
+
+    fn eat(&mut self, symbol: &'static str) -> bool {
+        if self.peek() == Some(&Token::Symbol(symbol)) {
+            self.next();
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, symbol: &'static str) -> Result<(), ImportError> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            Err(syntax(self.line, format!("expected {:?}", symbol)))
+        }
+    }
+
+    fn id(&mut self) -> Result<String, ImportError> {
+        match self.next() {
+            Some(Token::Id(id)) => Ok(id),
+            Some(Token::Symbol(s)) => Err(syntax(self.line, format!("unexpected {:?}", s))),
+            None => Err(syntax(self.line, "unexpected end of file")),
+        }
+    }
+
+    /// a node id, without the port if it has one
+    fn node_id(&mut self) -> Result<String, ImportError> {
+        let id = self.id()?;
+        while self.eat(":") {
+            self.id()?;
+        }
+        Ok(id)
+    }
+
+    /// any number of `[key = value, ...]` lists
+    fn attrs(&mut self) -> Result<Vec<(String, String)>, ImportError> {
+        let mut attrs = Vec::new();
+        while self.eat("[") {
+            while !self.eat("]") {
+                let key = self.id()?;
+                self.expect("=")?;
+                attrs.push((key, self.id()?));
+                if !self.eat(",") {
+                    self.eat(";");
+                }
+            }
+        }
+        Ok(attrs)
+    }
+}
+
+impl ImportedNetwork {
+    /// the format is picked by extension, a csv edge list can be given a csv of nest attributes
+    pub fn load(network: &Path, nodes: Option<&Path>) -> Result<Self, ImportError> {
+        let contents = fs::read_to_string(network)?;
+        let extension = network
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        let nodes = nodes.map(fs::read_to_string).transpose()?;
+        match (extension.as_deref(), nodes) {
+            (Some("csv" | "txt" | "edges"), nodes) => Self::from_csv(&contents, nodes.as_deref()),
+            (_, Some(_)) => Err(ImportError::Invalid(vec![
+                "a nodes file can only be given with a csv edge list".to_string(),
+            ])),
+            (Some("dot" | "gv"), None) => Self::from_dot(&contents),
+            (Some("graphml" | "xml"), None) => Self::from_graphml(&contents),
+            _ => Err(ImportError::Invalid(vec![format!(
+                "unknown format {}, expected .dot, .gv, .graphml or .csv",
+                network.display()
+            )])),
+        }
+    }
+
+    /// A graphviz graph. Nests are read from `pos`, `color` and `rate` attributes,
+    /// defaults set with `node [...]` and subgraphs aren't supported.
+    pub fn from_dot(contents: &str) -> Result<Self, ImportError> {
+        let mut parser = DotParser {
+            tokens: dot_tokens(contents)?.into_iter().peekable(),
+            line: 1,
+        };
+        let mut builder = Builder::default();
+
+        let mut keyword = parser.id()?;
+        if keyword.eq_ignore_ascii_case("strict") {
+            keyword = parser.id()?;
+        }
+        if !(keyword.eq_ignore_ascii_case("graph") || keyword.eq_ignore_ascii_case("digraph")) {
+            return Err(syntax(parser.line, "expected graph or digraph"));
+        }
+        if !parser.eat("{") {
+            parser.id()?;
+            parser.expect("{")?;
+        }
+        while !parser.eat("}") {
+            if parser.eat(";") {
+                continue;
+            }
+            if parser.peek() == Some(&Token::Symbol("{")) {
+                return Err(syntax(parser.line, "subgraphs aren't supported"));
+            }
+            let id = parser.node_id()?;
+            let lowercase = id.to_ascii_lowercase();
+            if lowercase == "subgraph" {
+                return Err(syntax(parser.line, "subgraphs aren't supported"));
+            }
+            if matches!(lowercase.as_str(), "graph" | "node" | "edge")
+                && parser.peek() == Some(&Token::Symbol("["))
+            {
+                // defaults, nothing the simulation uses
+                parser.attrs()?;
+                continue;
+            }
+            if parser.eat("=") {
+                // an attribute of the graph
+                parser.id()?;
+                continue;
+            }
+            let mut ids = vec![id];
+            while parser.eat("--") {
+                ids.push(parser.node_id()?);
+            }
+            let attrs = parser.attrs()?;
+            if ids.len() == 1 {
+                let nest = builder.nest(&ids[0]);
+                for (key, value) in attrs {
+                    builder.attr(nest, &key, &value);
+                }
+            } else {
+                for pair in ids.windows(2) {
+                    builder.edge(&pair[0], &pair[1]);
+                }
+            }
+        }
+        builder.finish()
+    }
+
+    /// A graphml graph. Nests are read from data keyed `x`, `y`, `pos`, `color` and `rate`
+    /// by their `attr.name`.
+    pub fn from_graphml(contents: &str) -> Result<Self, ImportError> {
+        let mut reader = Reader::from_str(contents);
+        reader.trim_text(true);
+        let line = |reader: &Reader<&[u8]>| contents[..reader.buffer_position()].lines().count();
+
+        let mut builder = Builder::default();
+        // attr.name of each key, by key id
+        let mut keys = BTreeMap::new();
+        let mut node = None;
+        let mut data = None;
+        loop {
+            let event = reader.read_event()?;
+            let (element, empty) = match &event {
+                Event::Start(element) => (element, false),
+                Event::Empty(element) => (element, true),
+                Event::Text(text) => {
+                    if let (Some(nest), Some(key)) = (node, &data) {
+                        builder.attr(nest, key, &text.unescape()?);
+                    }
+                    continue;
+                }
+                Event::End(element) => {
+                    match element.local_name().as_ref() {
+                        b"node" => node = None,
+                        b"data" => data = None,
+                        _ => {}
+                    }
+                    continue;
+                }
+                Event::Eof => break,
+                _ => continue,
+            };
+            let attrs = xml_attrs(element)?;
+            let attr = |name: &str| {
+                attrs
+                    .get(name)
+                    .ok_or_else(|| syntax(line(&reader), format!("missing {}", name)))
+            };
+            match element.local_name().as_ref() {
+                b"key" => {
+                    if let Some(name) = attrs.get("attr.name") {
+                        keys.insert(attr("id")?.clone(), name.clone());
+                    }
+                }
+                b"node" => {
+                    let nest = builder.nest(attr("id")?);
+                    if !empty {
+                        node = Some(nest);
+                    }
+                }
+                b"data" if !empty => {
+                    let key = attr("key")?;
+                    data = Some(keys.get(key).unwrap_or(key).clone());
+                }
+                b"edge" => builder.edge(attr("source")?, attr("target")?),
+                _ => {}
+            }
+        }
+        builder.finish()
+    }
+
+    /// An edge list with a `source,target` pair per line, the header is optional.
+    /// `nodes` lists nests with an `id` column and any of `x`, `y`, `color` and `rate`,
+    /// it decides the ids of the nests it lists.
+    pub fn from_csv(edges: &str, nodes: Option<&str>) -> Result<Self, ImportError> {
+        let mut builder = Builder::default();
+        if let Some(nodes) = nodes {
+            let mut lines = csv_lines(nodes);
+            let header: Vec<String> = match lines.next() {
+                Some((_, header)) => header.iter().map(|h| h.to_ascii_lowercase()).collect(),
+                None => return Err(syntax(1, "nodes file is empty")),
+            };
+            let id_column = header
+                .iter()
+                .position(|h| h == "id")
+                .ok_or_else(|| syntax(1, "nodes file needs an id column"))?;
+            for (line, fields) in lines {
+                let name = fields
+                    .get(id_column)
+                    .ok_or_else(|| syntax(line, "missing id"))?;
+                let nest = builder.nest(name);
+                for (key, value) in header.iter().zip(&fields) {
+                    if !value.is_empty() {
+                        builder.attr(nest, key, value);
+                    }
+                }
+            }
+        }
+        for (i, (line, fields)) in csv_lines(edges).enumerate() {
+            match fields.as_slice() {
+                [a, b, ..]
+                    if i == 0
+                        && is_header(a, &["source", "from"])
+                        && is_header(b, &["target", "to"]) => {}
+                [a, b, ..] => builder.edge(a, b),
+                _ => return Err(syntax(line, "expected source,target")),
+            }
+        }
+        builder.finish()
+    }
+
+    /// positions scaled to fit inside the arena, in id order, if the file had any
+    pub fn locs(&self, bounding_box: &BoundingBox, border_padding: f32) -> Option<Vec<Vec2>> {
+        let locs: Vec<Vec2> = self
+            .nests
+            .iter()
+            .map(ImportedNest::loc)
+            .collect::<Option<_>>()?;
+        let min = locs.iter().copied().reduce(Vec2::min)?;
+        let max = locs.iter().copied().reduce(Vec2::max)?;
+        let span = max - min;
+        let room = bounding_box.size() - 2. * border_padding;
+        // a file with every nest in a line only has to fit along it
+        let scale = [span.x, span.y]
+            .into_iter()
+            .zip([room.x, room.y])
+            .filter(|(span, _)| *span > 0.)
+            .map(|(span, room)| room / span)
+            .reduce(f32::min)
+            .unwrap_or(1.);
+        let center = (min + max) / 2.;
+        Some(locs.into_iter().map(|loc| (loc - center) * scale).collect())
+    }
+
+    pub fn topology(&self) -> Topology {
+        let mut topology = Topology::empty(self.nests.len());
+        for &(a, b) in &self.edges {
+            topology.add_edge(a, b);
+        }
+        topology
+    }
+}
+
+fn xml_attrs(element: &BytesStart) -> Result<BTreeMap<String, String>, ImportError> {
+    element
+        .attributes()
+        .map(|attr| {
+            let attr = attr.map_err(quick_xml::Error::from)?;
+            let key = String::from_utf8_lossy(attr.key.as_ref()).into_owned();
+            Ok((key, attr.unescape_value()?.into_owned()))
+        })
+        .collect()
+}
+
+/// whether a header field is one of `names`, in any case, i.e. gephi's `Source`
+fn is_header(field: &str, names: &[&str]) -> bool {
+    names.iter().any(|name| field.eq_ignore_ascii_case(name))
+}
+
+/// the trimmed fields of every line that isn't empty or a `#` comment, with its line number
+fn csv_lines(contents: &str) -> impl Iterator<Item = (usize, Vec<String>)> + '_ {
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(i, line)| {
+            let fields = line
+                .split(',')
+                .map(|field| field.trim().trim_matches('"').to_string())
+                .collect();
+            (i + 1, fields)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(network: &ImportedNetwork) -> Vec<&str> {
+        network.nests.iter().map(|n| n.name.as_str()).collect()
+    }
+
+    fn edges(network: &ImportedNetwork) -> Vec<(usize, usize)> {
+        network.edges.iter().map(|&(a, b)| (a.0, b.0)).collect()
+    }
+
+    fn problems(result: Result<ImportedNetwork, ImportError>) -> Vec<String> {
+        match result {
+            Err(ImportError::Invalid(problems)) => problems,
+            other => panic!("expected an invalid network, got {:?}", other),
+        }
+    }
+
+    fn syntax_line(result: Result<ImportedNetwork, ImportError>) -> usize {
+        match result {
+            Err(ImportError::Syntax { line, .. }) => line,
+            other => panic!("expected a syntax error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn dot_quoted_ids() {
+        let network = ImportedNetwork::from_dot(
+            r#"graph { "say \"hi\"" -- "long\
+name" }"#,
+        )
+        .unwrap();
+        assert_eq!(names(&network), ["say \"hi\"", "longname"]);
+    }
+
+    #[test]
+    fn dot_comments() {
+        let network = ImportedNetwork::from_dot(
+            "# made by hand\n\
+             graph {\n\
+             /* a -- b\n\
+             */ c -- d // c -- e\n\
+             }",
+        )
+        .unwrap();
+        assert_eq!(names(&network), ["c", "d"]);
+        assert_eq!(edges(&network), [(0, 1)]);
+    }
+
+    #[test]
+    fn dot_syntax_errors_have_their_line() {
+        let result = ImportedNetwork::from_dot("graph {\n/* one\ntwo */\na -- ;\n}");
+        assert_eq!(syntax_line(result), 4);
+        let result = ImportedNetwork::from_dot("graph {\n\"a\nb\" -- c\n/* never closed");
+        assert_eq!(syntax_line(result), 4);
+    }
+
+    #[test]
+    fn dot_edge_chains() {
+        let network = ImportedNetwork::from_dot("graph { a -- b -- c; c -- a }").unwrap();
+        assert_eq!(edges(&network), [(0, 1), (1, 2), (2, 0)]);
+        let network = ImportedNetwork::from_dot("strict digraph net { a -> b -> c }").unwrap();
+        assert_eq!(edges(&network), [(0, 1), (1, 2)]);
+    }
+
+    #[test]
+    fn dot_defaults_dont_make_nests() {
+        let network = ImportedNetwork::from_dot(
+            "graph {\n\
+             graph [splines=true]; node [shape=circle]; edge [color=red]\n\
+             rankdir=LR\n\
+             a -- b [weight=2]\n\
+             }",
+        )
+        .unwrap();
+        assert_eq!(names(&network), ["a", "b"]);
+        assert_eq!(edges(&network), [(0, 1)]);
+    }
+
+    #[test]
+    fn dot_ports_are_ignored() {
+        let network = ImportedNetwork::from_dot("graph { a:n -- b:port:s }").unwrap();
+        assert_eq!(names(&network), ["a", "b"]);
+        assert_eq!(edges(&network), [(0, 1)]);
+    }
+
+    #[test]
+    fn dot_attributes() {
+        let network = ImportedNetwork::from_dot(
+            r##"graph { a [pos="1,2!", color="#ff8800", rate=0.5]; b [pos="3,4"] }"##,
+        )
+        .unwrap();
+        let a = &network.nests[0];
+        assert_eq!(a.loc(), Some(Vec2::new(1., 2.)));
+        assert_eq!(a.color, Some(Color::hex("ff8800").unwrap()));
+        assert_eq!(a.request_prob, Some(0.5));
+        assert_eq!(network.nests[1].loc(), Some(Vec2::new(3., 4.)));
+        assert_eq!(network.nests[1].request_prob, None);
+    }
+
+    #[test]
+    fn dot_invalid_attributes() {
+        let result = ImportedNetwork::from_dot("graph { a [rate=2]; b [pos=\"x\"] }");
+        assert_eq!(
+            problems(result),
+            [
+                "nest a: rate should be between 0 and 1 (got \"2\")",
+                "nest b: pos should be x,y (got \"x\")",
+            ]
+        );
+    }
+
+    #[test]
+    fn either_all_or_no_nests_have_a_position() {
+        let result = ImportedNetwork::from_dot("graph { a [pos=\"0,0\"]; b -- c }");
+        assert_eq!(
+            problems(result),
+            ["1 of 3 nests have a position, either all or none should"]
+        );
+    }
+
+    #[test]
+    fn graphml_keys_map_to_attr_names() {
+        let network = ImportedNetwork::from_graphml(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+            <graphml xmlns="http://graphml.graphdrawing.org/xmlns">
+              <key id="d0" for="node" attr.name="x" attr.type="double"/>
+              <key id="d1" for="node" attr.name="y" attr.type="double"/>
+              <graph edgedefault="undirected">
+                <node id="a">
+                  <data key="d0">0</data>
+                  <data key="d1">5</data>
+                  <data key="rate">0.25</data>
+                </node>
+                <node id="b"><data key="d0">10</data><data key="d1">0</data></node>
+                <edge source="a" target="b"/>
+              </graph>
+            </graphml>"#,
+        )
+        .unwrap();
+        assert_eq!(names(&network), ["a", "b"]);
+        assert_eq!(edges(&network), [(0, 1)]);
+        assert_eq!(network.nests[0].loc(), Some(Vec2::new(0., 5.)));
+        assert_eq!(network.nests[0].request_prob, Some(0.25));
+        assert_eq!(network.nests[1].loc(), Some(Vec2::new(10., 0.)));
+    }
+
+    #[test]
+    fn csv_header_is_optional() {
+        for edges_csv in [
+            "a,b\nb,c\n",
+            "source,target\na,b\nb,c\n",
+            "Source,Target\na,b\nb,c\n",
+        ] {
+            let network = ImportedNetwork::from_csv(edges_csv, None).unwrap();
+            assert_eq!(names(&network), ["a", "b", "c"], "{:?}", edges_csv);
+            assert_eq!(edges(&network), [(0, 1), (1, 2)], "{:?}", edges_csv);
+        }
+    }
+
+    #[test]
+    fn csv_nodes_decide_ids() {
+        let network = ImportedNetwork::from_csv(
+            "from,to\n# comment\nb,c\n",
+            Some("Id,X,Y,Color,Rate\nc,0,0,#ff8800,0.02\nb,100,0,,\n"),
+        )
+        .unwrap();
+        assert_eq!(names(&network), ["c", "b"]);
+        assert_eq!(edges(&network), [(1, 0)]);
+        assert_eq!(network.nests[0].color, Some(Color::hex("ff8800").unwrap()));
+        assert_eq!(network.nests[0].request_prob, Some(0.02));
+        assert_eq!(network.nests[1].loc(), Some(Vec2::new(100., 0.)));
+        assert_eq!(network.nests[1].color, None);
+    }
+
+    #[test]
+    fn locs_fit_collinear_nests() {
+        let arena = BoundingBox { w: 1000., h: 500. };
+        let network =
+            ImportedNetwork::from_dot(r#"graph { a [pos="0,0"]; b [pos="10,0"]; c [pos="20,0"] }"#)
+                .unwrap();
+        assert_eq!(
+            network.locs(&arena, 50.),
+            Some(vec![
+                Vec2::new(-450., 0.),
+                Vec2::new(0., 0.),
+                Vec2::new(450., 0.)
+            ])
+        );
+        let network = ImportedNetwork::from_dot(r#"graph { a [pos="7,3"] }"#).unwrap();
+        assert_eq!(network.locs(&arena, 50.), Some(vec![Vec2::ZERO]));
+        let network = ImportedNetwork::from_dot("graph { a -- b }").unwrap();
+        assert_eq!(network.locs(&arena, 50.), None);
+    }
+}
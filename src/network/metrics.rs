@@ -0,0 +1,512 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt,
+};
+
+use bevy::{app::AppExit, log, prelude::*};
+
+use super::{
+    events::{
+        DeliveryCompleted, FailureReason, HopFallback, RequestFailed, RequestIssued, TargetReached,
+    },
+    nest::NestId,
+    RequestStats,
+};
+
+/// number of most recent requests the rolling aggregates cover
+pub const ROLLING_WINDOW: usize = 200;
+/// bins of the hop count histogram logged on exit
+const HISTOGRAM_BINS: usize = 10;
+
+/// Every value of one measurement, i.e. hop count, or only the most recent ones
+/// when it has a window.
+#[derive(Debug, Clone, Default)]
+pub struct Summary {
+    values: VecDeque<f32>,
+    window: Option<usize>,
+}
+
+impl Summary {
+    pub fn rolling(window: usize) -> Self {
+        Self {
+            values: VecDeque::with_capacity(window),
+            window: Some(window),
+        }
+    }
+
+    pub fn push(&mut self, value: f32) {
+        if let Some(window) = self.window {
+            if self.values.len() == window {
+                self.values.pop_front();
+            }
+        }
+        self.values.push_back(value);
+    }
+
+    pub fn count(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn mean(&self) -> Option<f32> {
+        if self.is_empty() {
+            return None;
+        }
+        Some(self.values.iter().sum::<f32>() / self.count() as f32)
+    }
+
+    pub fn min(&self) -> Option<f32> {
+        self.values.iter().copied().reduce(f32::min)
+    }
+
+    pub fn max(&self) -> Option<f32> {
+        self.values.iter().copied().reduce(f32::max)
+    }
+
+    /// nearest rank percentile, `p` between 0 and 100
+    pub fn percentile(&self, p: f32) -> Option<f32> {
+        if self.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<f32> = self.values.iter().copied().collect();
+        sorted.sort_unstable_by(f32::total_cmp);
+        let rank = (p.clamp(0., 100.) / 100. * sorted.len() as f32).ceil() as usize;
+        Some(sorted[rank.saturating_sub(1)])
+    }
+
+    /// counts of values in `bins` equal width bins spanning min to max
+    pub fn histogram(&self, bins: usize) -> Histogram {
+        let (min, max) = match (self.min(), self.max()) {
+            (Some(min), Some(max)) => (min, max),
+            _ => (0., 0.),
+        };
+        let bins = bins.max(1);
+        let bin_width = ((max - min) / bins as f32).max(f32::EPSILON);
+        let mut counts = vec![0; bins];
+        for v in &self.values {
+            let bin = ((v - min) / bin_width) as usize;
+            counts[bin.min(bins - 1)] += 1;
+        }
+        Histogram {
+            min,
+            bin_width,
+            counts,
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (
+            self.mean(),
+            self.percentile(50.),
+            self.percentile(90.),
+            self.percentile(99.),
+        ) {
+            (Some(mean), Some(p50), Some(p90), Some(p99)) => write!(
+                f,
+                "mean {:.2} p50 {:.2} p90 {:.2} p99 {:.2}",
+                mean, p50, p90, p99
+            ),
+            _ => write!(f, "-"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    /// lower edge of the first bin
+    pub min: f32,
+    pub bin_width: f32,
+    pub counts: Vec<u32>,
+}
+
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.counts.iter().sum::<u32>().max(1) as f32;
+        for (i, count) in self.counts.iter().enumerate() {
+            let low = self.min + i as f32 * self.bin_width;
+            write!(
+                f,
+                "\n  {:>8.1} - {:<8.1} {:>6} {}",
+                low,
+                low + self.bin_width,
+                count,
+                "#".repeat((*count as f32 / total * 40.).round() as usize)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Aggregates over a set of requests.
+/// `success` holds a 1 for every delivered request and a 0 for every failed one,
+/// so its mean is the success rate.
+#[derive(Debug, Clone, Default)]
+pub struct RequestMetrics {
+    pub success: Summary,
+    /// hops from the origin to the target
+    pub hops: Summary,
+    /// distance travelled there and back
+    pub distance: Summary,
+    /// simulated seconds from the request being issued to the target being found
+    pub time_to_target: Summary,
+    /// simulated seconds from the request being issued to the data being delivered
+    pub round_trip: Summary,
+}
+
+impl RequestMetrics {
+    fn rolling(window: usize) -> Self {
+        Self {
+            success: Summary::rolling(window),
+            hops: Summary::rolling(window),
+            distance: Summary::rolling(window),
+            time_to_target: Summary::rolling(window),
+            round_trip: Summary::rolling(window),
+        }
+    }
+
+    pub fn success_rate(&self) -> Option<f32> {
+        self.success.mean()
+    }
+
+    fn record(&mut self, request: &InFlight, outcome: &Outcome) {
+        match *outcome {
+            Outcome::Delivered {
+                distance,
+                round_trip,
+            } => {
+                self.success.push(1.);
+                if let Some((hops, time)) = request.reached {
+                    self.hops.push(hops as f32);
+                    self.time_to_target.push(time);
+                }
+                self.distance.push(distance);
+                self.round_trip.push(round_trip);
+            }
+            Outcome::Failed => self.success.push(0.),
+        }
+    }
+}
+
+impl fmt::Display for RequestMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let success_rate = self.success_rate().unwrap_or(0.) * 100.;
+        writeln!(
+            f,
+            "requests: {} success: {:.1}%",
+            self.success.count(),
+            success_rate
+        )?;
+        writeln!(f, "  hops:           {}", self.hops)?;
+        writeln!(f, "  distance:       {}", self.distance)?;
+        writeln!(f, "  time to target: {}", self.time_to_target)?;
+        write!(f, "  round trip:     {}", self.round_trip)
+    }
+}
+
+/// a request that hasn't been delivered or failed yet
+#[derive(Debug, Clone)]
+struct InFlight {
+    origin: NestId,
+    target: NestId,
+    /// hops and simulated seconds it took to find the target
+    reached: Option<(u32, f32)>,
+}
+
+enum Outcome {
+    Delivered { distance: f32, round_trip: f32 },
+    Failed,
+}
+
+/// Follows every request from being issued until it's delivered or fails.
+/// Aggregates are kept for all requests, the most recent [`ROLLING_WINDOW`] requests
+/// and every (origin, target) pair of nests.
+#[derive(Debug, Resource)]
+pub struct Metrics {
+    pub cumulative: RequestMetrics,
+    pub rolling: RequestMetrics,
+    pub per_pair: BTreeMap<(NestId, NestId), RequestMetrics>,
+    in_flight: BTreeMap<Entity, InFlight>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            cumulative: RequestMetrics::default(),
+            rolling: RequestMetrics::rolling(ROLLING_WINDOW),
+            per_pair: BTreeMap::new(),
+            in_flight: BTreeMap::new(),
+        }
+    }
+}
+
+impl Metrics {
+    /// requests that haven't finished yet
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    fn finish(&mut self, ant: Entity, outcome: Outcome) {
+        if let Some(request) = self.in_flight.remove(&ant) {
+            self.cumulative.record(&request, &outcome);
+            self.rolling.record(&request, &outcome);
+            self.per_pair
+                .entry((request.origin, request.target))
+                .or_default()
+                .record(&request, &outcome);
+        }
+    }
+}
+
+/// Events in the order they were sent during a tick.
+/// Pooled ants can finish one request and start another in the same tick, so
+/// events have to be replayed in order rather than one event type at a time.
+enum RequestEvent<'a> {
+    Reached(&'a TargetReached),
+    Delivered(&'a DeliveryCompleted),
+    Failed(&'a RequestFailed),
+    Issued(&'a RequestIssued),
+}
+
+impl RequestEvent<'_> {
+    /// tick, then the order of the systems that send them within a tick
+    fn order(&self) -> (u64, u8) {
+        match self {
+            RequestEvent::Reached(e) => (e.tick, 0),
+            RequestEvent::Delivered(e) => (e.tick, 0),
+            RequestEvent::Failed(e) => (e.tick, 1),
+            RequestEvent::Issued(e) => (e.tick, 2),
+        }
+    }
+}
+
+/// updates both the [`Metrics`] and the [`RequestStats`] so their counts always agree
+pub fn record_metrics(
+    mut issued: EventReader<RequestIssued>,
+    mut reached: EventReader<TargetReached>,
+    mut delivered: EventReader<DeliveryCompleted>,
+    mut failed: EventReader<RequestFailed>,
+    mut fallbacks: EventReader<HopFallback>,
+    mut metrics: ResMut<Metrics>,
+    mut stats: ResMut<RequestStats>,
+) {
+    stats.fallbacks += fallbacks.iter().count() as u64;
+
+    let mut events: Vec<RequestEvent> = reached
+        .iter()
+        .map(RequestEvent::Reached)
+        .chain(delivered.iter().map(RequestEvent::Delivered))
+        .chain(failed.iter().map(RequestEvent::Failed))
+        .chain(issued.iter().map(RequestEvent::Issued))
+        .collect();
+    // stable so events of the same kind keep the order they were sent in
+    events.sort_by_key(RequestEvent::order);
+
+    for event in events {
+        match event {
+            RequestEvent::Issued(e) => {
+                stats.issued += 1;
+                metrics.in_flight.insert(
+                    e.ant,
+                    InFlight {
+                        origin: e.origin,
+                        target: e.target,
+                        reached: None,
+                    },
+                );
+            }
+            RequestEvent::Reached(e) => {
+                if let Some(request) = metrics.in_flight.get_mut(&e.ant) {
+                    request.reached = Some((e.hops, e.elapsed));
+                }
+            }
+            RequestEvent::Delivered(e) => {
+                stats.completed += 1;
+                metrics.finish(
+                    e.ant,
+                    Outcome::Delivered {
+                        distance: e.distance,
+                        round_trip: e.elapsed,
+                    },
+                );
+            }
+            RequestEvent::Failed(e) => {
+                match e.reason {
+                    FailureReason::HopLimit => stats.failed_hops += 1,
+                    FailureReason::Ttl => stats.failed_ttl += 1,
+                }
+                metrics.finish(e.ant, Outcome::Failed);
+            }
+        }
+    }
+}
+
+pub fn log_metrics_on_exit(exit: EventReader<AppExit>, metrics: Res<Metrics>) {
+    if !exit.is_empty() {
+        log::info!("all {}", metrics.cumulative);
+        if !metrics.cumulative.hops.is_empty() {
+            log::info!("hops:{}", metrics.cumulative.hops.histogram(HISTOGRAM_BINS));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::{event::Event, system::System};
+
+    use super::*;
+
+    fn summary(values: impl IntoIterator<Item = f32>, window: Option<usize>) -> Summary {
+        let mut summary = match window {
+            Some(window) => Summary::rolling(window),
+            None => Summary::default(),
+        };
+        for value in values {
+            summary.push(value);
+        }
+        summary
+    }
+
+    #[test]
+    fn percentile_is_nearest_rank() {
+        let summary = summary((1..=10).map(|v| v as f32), None);
+        assert_eq!(summary.percentile(0.), Some(1.));
+        assert_eq!(summary.percentile(10.), Some(1.));
+        assert_eq!(summary.percentile(11.), Some(2.));
+        assert_eq!(summary.percentile(50.), Some(5.));
+        assert_eq!(summary.percentile(90.), Some(9.));
+        assert_eq!(summary.percentile(99.), Some(10.));
+        assert_eq!(summary.percentile(100.), Some(10.));
+        assert_eq!(summary.percentile(150.), Some(10.));
+        assert_eq!(Summary::default().percentile(50.), None);
+    }
+
+    #[test]
+    fn rolling_window_drops_the_oldest() {
+        let summary = summary([1., 2., 3., 4., 5.], Some(3));
+        assert_eq!(summary.count(), 3);
+        assert_eq!(summary.min(), Some(3.));
+        assert_eq!(summary.max(), Some(5.));
+        assert_eq!(summary.mean(), Some(4.));
+    }
+
+    #[test]
+    fn histogram_spans_min_to_max() {
+        let histogram = summary([0., 1., 2., 3., 4.], None).histogram(2);
+        assert_eq!(histogram.min, 0.);
+        assert_eq!(histogram.bin_width, 2.);
+        assert_eq!(histogram.counts, [2, 3]);
+        assert_eq!(summary([7., 7.], None).histogram(3).counts, [2, 0, 0]);
+        assert_eq!(Summary::default().histogram(2).counts, [0, 0]);
+    }
+
+    struct Requests {
+        world: World,
+        system: Box<dyn System<In = (), Out = ()>>,
+    }
+
+    impl Requests {
+        fn new() -> Self {
+            let mut world = World::new();
+            world.init_resource::<Events<RequestIssued>>();
+            world.init_resource::<Events<TargetReached>>();
+            world.init_resource::<Events<DeliveryCompleted>>();
+            world.init_resource::<Events<RequestFailed>>();
+            world.init_resource::<Events<HopFallback>>();
+            world.init_resource::<Metrics>();
+            world.init_resource::<RequestStats>();
+            let mut system: Box<dyn System<In = (), Out = ()>> =
+                Box::new(IntoSystem::into_system(record_metrics));
+            system.initialize(&mut world);
+            Self { world, system }
+        }
+
+        fn send<E: Event>(&mut self, event: E) {
+            self.world.resource_mut::<Events<E>>().send(event);
+        }
+
+        fn issue(&mut self, ant: Entity, origin: usize, target: usize, tick: u64) {
+            self.send(RequestIssued {
+                ant,
+                origin: NestId(origin),
+                target: NestId(target),
+                tick,
+            });
+        }
+
+        fn record(&mut self) {
+            self.system.run((), &mut self.world);
+        }
+
+        fn metrics(&self) -> &Metrics {
+            self.world.resource::<Metrics>()
+        }
+    }
+
+    #[test]
+    fn delivered_and_reissued_in_one_tick() {
+        let ant = Entity::from_raw(0);
+        let mut requests = Requests::new();
+        requests.issue(ant, 0, 1, 1);
+        requests.record();
+
+        // the pooled ant is handed a new request on the tick it delivers,
+        // the issue is sent first but happened last
+        requests.issue(ant, 2, 3, 5);
+        requests.send(DeliveryCompleted {
+            ant,
+            origin: NestId(0),
+            target: NestId(1),
+            hops: 4,
+            distance: 100.,
+            tick: 5,
+            elapsed: 2.,
+        });
+        requests.send(TargetReached {
+            ant,
+            origin: NestId(0),
+            target: NestId(1),
+            hops: 2,
+            distance: 50.,
+            tick: 3,
+            elapsed: 1.,
+        });
+        requests.record();
+
+        let metrics = requests.metrics();
+        assert_eq!(metrics.in_flight(), 1);
+        let delivered = &metrics.per_pair[&(NestId(0), NestId(1))];
+        assert_eq!(delivered.success_rate(), Some(1.));
+        assert_eq!(delivered.hops.mean(), Some(2.));
+        assert_eq!(delivered.round_trip.mean(), Some(2.));
+        assert!(!metrics.per_pair.contains_key(&(NestId(2), NestId(3))));
+
+        requests.send(RequestFailed {
+            ant,
+            origin: NestId(2),
+            target: NestId(3),
+            reached_target: false,
+            reason: FailureReason::Ttl,
+            hops: 1,
+            distance: 10.,
+            tick: 6,
+            elapsed: 0.5,
+        });
+        requests.record();
+
+        let metrics = requests.metrics();
+        assert_eq!(metrics.in_flight(), 0);
+        assert_eq!(metrics.cumulative.success.count(), 2);
+        assert_eq!(metrics.cumulative.success_rate(), Some(0.5));
+        assert_eq!(
+            metrics.per_pair[&(NestId(2), NestId(3))].success_rate(),
+            Some(0.)
+        );
+        let stats = requests.world.resource::<RequestStats>();
+        assert_eq!((stats.issued, stats.completed, stats.failed()), (2, 1, 1));
+    }
+}
@@ -1,39 +1,146 @@
 pub mod ant;
+pub mod dot;
+pub mod events;
+pub mod export;
 pub mod food;
+pub mod import;
+pub mod metrics;
 pub mod nest;
 pub mod pheromones;
+pub mod replay;
+pub mod routing;
+pub mod snapshot;
+pub mod topology;
 
 use std::time::Duration;
 
-use crate::{Colors, GameMode, GameState, NumAnts};
+use crate::{
+    clock::{self, PendingSteps, SimClock, TimeScale, SIM_TICK},
+    config::SimConfig,
+    ArenaSettings, BoundingBox, Colors, GameMode, NumAnts, SimRng, ANT_SPEED, BORDER_PADDING,
+    MAX_ANTS, NEST_FOOD_REQUEST_PROB, NUM_NESTS,
+};
 use bevy::prelude::*;
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
 use iyes_loopless::prelude::*;
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
 
-use self::pheromones::{PheromoneGrid, PheromoneManager};
+use self::{
+    nest::NestId,
+    pheromones::{PheromoneGrid, PheromoneManager, PHEROMONE_GRANULARITY},
+};
 
-pub struct AntNetworkPlugin;
+#[derive(Default)]
+pub struct AntNetworkPlugin {
+    /// skip everything that needs a window (inspector, textures)
+    pub headless: bool,
+    pub config: SimConfig,
+}
 
 impl Plugin for AntNetworkPlugin {
     fn build(&self, app: &mut App) {
+        if self.headless {
+            app.insert_resource(ant::AntTexture(Handle::default()));
+        } else {
+            app.add_plugin(WorldInspectorPlugin)
+                .add_startup_system(ant::load_ant_texture);
+        }
+        // before anything that is sized from them
+        app.insert_resource(self.config.network.clone())
+            .insert_resource(self.config.decision_weights.clone())
+            .insert_resource(self.config.pheromones.clone())
+            .insert_resource(self.config.limits.clone())
+            .insert_resource(self.config.topology.clone());
+        if let Some(seed) = self.config.seed {
+            app.insert_resource(SimRng::seed_from_u64(seed));
+        }
         app.init_resource::<Colors>()
             .init_resource::<NumAnts>()
-            .init_resource::<DecisionWeights>()
-            .init_resource::<PheromoneParams>()
-            .add_plugin(WorldInspectorPlugin)
+            .init_resource::<ant::AntPool>()
+            .init_resource::<RequestStats>()
+            .init_resource::<BoundingBox>()
+            .init_resource::<ArenaSettings>()
+            .init_resource::<SimRng>()
+            .register_type::<NetworkParams>()
+            .register_type::<DecisionWeights>()
             .register_type::<PheromoneParams>()
+            .register_type::<RequestLimits>()
+            .register_type::<topology::TopologyParams>()
+            .register_type::<RequestStats>()
             .register_type::<Colors>()
             .register_type::<NumAnts>()
+            .register_type::<ant::AntPool>()
+            .register_type::<BoundingBox>()
+            .register_type::<ArenaSettings>()
             .register_type::<PheromoneManager>()
             .register_type::<PheromoneGrid>()
             .add_startup_system(pheromones::create_pheromone_manager)
             .add_startup_system(nest::spawn_nests)
-            .add_startup_system(ant::load_ant_texture)
-            .add_system_set(
+            .add_event::<events::RequestIssued>()
+            .add_event::<events::AntVisitedNest>()
+            .add_event::<events::TargetReached>()
+            .add_event::<events::DeliveryCompleted>()
+            .add_event::<events::RequestFailed>()
+            .add_event::<events::HopFallback>()
+            .add_system(events::log_events)
+            .add_event::<snapshot::SaveSnapshot>()
+            .add_event::<snapshot::LoadSnapshot>()
+            .add_system(snapshot::handle_snapshots)
+            .init_resource::<metrics::Metrics>()
+            .init_resource::<routing::RoutingPolicies>()
+            .init_resource::<dot::EdgeTraffic>()
+            .add_event::<dot::ExportDot>()
+            .add_system(dot::export_dot)
+            .init_resource::<topology::EdgeAssets>()
+            .add_system(topology::draw_edges)
+            // the app stops after the update that sent AppExit, so check for it at the very end
+            .add_system_to_stage(CoreStage::Last, metrics::log_metrics_on_exit);
+
+        // everything that changes the state of the simulation runs once per tick
+        let tick = app
+            .world
+            .get_resource_or_insert_with(SimClock::default)
+            .step();
+        app.init_resource::<TimeScale>()
+            .init_resource::<PendingSteps>()
+            .register_type::<SimClock>()
+            .register_type::<TimeScale>()
+            .register_type::<PendingSteps>()
+            .add_system(clock::apply_time_scale)
+            .add_fixed_timestep(tick, SIM_TICK)
+            .add_fixed_timestep_system_set(
+                SIM_TICK,
+                0,
+                // Changes made between ticks, i.e. from the inspector or by resizing the window,
+                // take effect right before the next tick, even while paused. Anything that
+                // replays changes on the tick they were made runs before these.
                 ConditionSet::new()
-                    .run_in_state(GameState::Play)
                     .run_in_state(GameMode::AntNetwork)
-                    .with_system(nest::food_request_system)
+                    .label("network changes")
+                    .with_system(pheromones::resize_pheromone_grid)
+                    .with_system(nest::relayout_nests)
+                    .with_system(nest::add_nests)
+                    .with_system(routing::check_routing_policy)
+                    .into(),
+            )
+            .add_fixed_timestep_system_set(
+                SIM_TICK,
+                0,
+                // after the nests have been moved to where they'll be
+                ConditionSet::new()
+                    .run_in_state(GameMode::AntNetwork)
+                    .after("network changes")
+                    .with_system(topology::regenerate_topology)
+                    .into(),
+            )
+            .add_fixed_timestep_child_stage(SIM_TICK)
+            .add_fixed_timestep_system_set(
+                SIM_TICK,
+                1,
+                ConditionSet::new()
+                    .run_if(clock::sim_running)
+                    .run_in_state(GameMode::AntNetwork)
                     // .with_system(ant::move_ant_network.label("move ants"))
                     // .with_system(pheromones::color_and_fade_pheromones.label("color pheromones").after("move ants"))
                     // .with_system(pheromones::color_and_fade_pheromones)
@@ -41,41 +148,119 @@ impl Plugin for AntNetworkPlugin {
                     .with_system(pheromones::fade_pheromones)
                     .into(),
             )
-            .add_system_set(
+            .add_fixed_timestep_system_set(
+                SIM_TICK,
+                1,
                 ConditionSet::new()
-                    .run_in_state(GameState::Play)
+                    .run_if(clock::sim_running)
                     .run_in_state(GameMode::AntNetwork)
                     .label("collisions")
                     .before("move ants")
                     .with_system(nest::ant_nest_network_interactions)
                     .into(),
             )
-            .add_system_set(
+            .add_fixed_timestep_system_set(
+                SIM_TICK,
+                1,
                 ConditionSet::new()
-                    .run_in_state(GameState::Play)
+                    .run_if(clock::sim_running)
                     .run_in_state(GameMode::AntNetwork)
                     .label("move ants")
                     .after("collisions")
                     .with_system(ant::move_ant)
                     .into(),
             )
-            .add_fixed_framestep(30, "color timestep")
-            .add_fixed_framestep_system(
-                "color timestep",
-                0,
-                pheromones::create_required_pheromones
-                    .run_in_state(GameState::Play)
-                    .run_in_state(GameMode::AntNetwork),
+            .add_fixed_timestep_system_set(
+                SIM_TICK,
+                1,
+                // before requests so the freed up ants can be reused straight away
+                ConditionSet::new()
+                    .run_if(clock::sim_running)
+                    .run_in_state(GameMode::AntNetwork)
+                    .label("expire requests")
+                    .after("move ants")
+                    .before("requests")
+                    .with_system(ant::expire_requests)
+                    .into(),
             )
-            .add_fixed_framestep_child_stage("color timestep")
-            .add_fixed_framestep_system(
-                "color timestep",
+            .add_fixed_timestep_system_set(
+                SIM_TICK,
                 1,
-                pheromones::leave_pheromone_trails
-                    .run_in_state(GameState::Play)
+                // both draw from the SimRng so they need a fixed order to be reproducible
+                ConditionSet::new()
+                    .run_if(clock::sim_running)
+                    .run_in_state(GameMode::AntNetwork)
+                    .label("requests")
+                    .after("move ants")
+                    .with_system(nest::food_request_system)
+                    .into(),
+            )
+            // trails are only laid every TRAIL_TICKS ticks, in two stages so newly
+            // spawned pheromones exist by the time they're colored
+            .add_fixed_timestep_child_stage(SIM_TICK)
+            .add_fixed_timestep_system(
+                SIM_TICK,
+                2,
+                pheromones::create_required_pheromones
+                    .run_if(clock::sim_running)
+                    .run_if(trail_tick)
                     .run_in_state(GameMode::AntNetwork),
+            )
+            .add_fixed_timestep_child_stage(SIM_TICK)
+            .add_fixed_timestep_system_set(
+                SIM_TICK,
+                3,
+                ConditionSet::new()
+                    .run_if(clock::sim_running)
+                    .run_if(trail_tick)
+                    .run_in_state(GameMode::AntNetwork)
+                    .label("trails")
+                    .with_system(pheromones::leave_pheromone_trails)
+                    .into(),
+            )
+            .add_fixed_timestep_system_set(
+                SIM_TICK,
+                3,
+                // once the tick's events have all been sent
+                ConditionSet::new()
+                    .run_if(clock::sim_running)
+                    .run_in_state(GameMode::AntNetwork)
+                    .label("metrics")
+                    .after("trails")
+                    .with_system(metrics::record_metrics)
+                    .into(),
+            )
+            .add_fixed_timestep_system_set(
+                SIM_TICK,
+                3,
+                // last so every system in the tick sees the same tick number
+                ConditionSet::new()
+                    .run_if(clock::sim_running)
+                    .run_in_state(GameMode::AntNetwork)
+                    .label("advance clock")
+                    .after("metrics")
+                    .with_system(clock::advance_clock)
+                    .into(),
             );
 
+        // opened by whoever adds the plugin, so a file that can't be created stops the run
+        // before it starts
+        if app.world.contains_resource::<export::Exporter>() {
+            app.add_fixed_timestep_system_set(
+                SIM_TICK,
+                3,
+                ConditionSet::new()
+                    .run_if(clock::sim_running)
+                    .run_in_state(GameMode::AntNetwork)
+                    .label("export")
+                    .after("metrics")
+                    .before("advance clock")
+                    .with_system(export::export_samples)
+                    .into(),
+            )
+            .add_system_to_stage(CoreStage::Last, export::flush_on_exit);
+        }
+
         // .add_system_set(
         //     ConditionSet::new()
         // .run_in_state(GameState::Paused)
@@ -86,16 +271,108 @@ impl Plugin for AntNetworkPlugin {
     }
 }
 
+/// number of ticks between ants laying down trail pheromones
+const TRAIL_TICKS: u64 = 30;
+
+fn trail_tick(clock: Res<SimClock>) -> bool {
+    clock.tick % TRAIL_TICKS == 0
+}
+
+/// sizes and rates of the network, read once when the things they describe are created
+#[derive(Debug, Clone, PartialEq, Resource, Reflect, Serialize, Deserialize)]
+#[reflect(Resource)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkParams {
+    /// can be raised while running to add nests
+    pub num_nests: usize,
+    pub max_ants: u32,
+    pub ant_speed: f32,
+    /// chance of each nest requesting food every tick
+    pub food_request_prob: f32,
+    /// allowed distance to the edge of the arena
+    pub border_padding: f32,
+    /// side length of a pheromone grid cell
+    pub pheromone_granularity: u32,
+}
+
+impl Default for NetworkParams {
+    fn default() -> Self {
+        Self {
+            num_nests: NUM_NESTS,
+            max_ants: MAX_ANTS,
+            ant_speed: ANT_SPEED,
+            food_request_prob: NEST_FOOD_REQUEST_PROB,
+            border_padding: BORDER_PADDING,
+            pheromone_granularity: PHEROMONE_GRANULARITY,
+        }
+    }
+}
+
+const MAX_HOPS: u32 = 50;
+const REQUEST_TTL: f32 = 60.;
+
+/// how long a request is given to find its target before it counts as failed
+#[derive(Debug, Clone, PartialEq, Resource, Reflect, Serialize, Deserialize)]
+#[reflect(Resource)]
+#[serde(default, deny_unknown_fields)]
+pub struct RequestLimits {
+    /// nests an ant may pass through, 0 for no limit
+    pub max_hops: u32,
+    /// simulated seconds before an ant gives up, 0 for no limit
+    pub ttl: f32,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        Self {
+            max_hops: MAX_HOPS,
+            ttl: REQUEST_TTL,
+        }
+    }
+}
+
+/// running totals of what happened to every request, counted by [`metrics::record_metrics`]
+#[derive(Debug, Default, Clone, Resource, Reflect, Serialize, Deserialize)]
+#[reflect(Resource)]
+pub struct RequestStats {
+    pub issued: u64,
+    /// made it to the target and back
+    pub completed: u64,
+    pub failed_hops: u64,
+    pub failed_ttl: u64,
+    /// hops chosen by [`FallbackPolicy`] because the decision weights couldn't be sampled
+    pub fallbacks: u64,
+}
+
+impl RequestStats {
+    pub fn failed(&self) -> u64 {
+        self.failed_hops + self.failed_ttl
+    }
+
+    /// fraction of finished requests that completed
+    pub fn success_rate(&self) -> f32 {
+        let finished = self.completed + self.failed();
+        if finished == 0 {
+            return 0.0;
+        }
+        self.completed as f32 / finished as f32
+    }
+}
+
 const DISTANCE_POW: f32 = 1.2;
 const PHEROMONE_POW: f32 = 4.;
 const VISITED_POW: f32 = 2.;
 
-#[derive(Debug, Clone, Resource, Reflect)]
+#[derive(Debug, Clone, PartialEq, Resource, Reflect, Serialize, Deserialize)]
 #[reflect(Resource)]
+#[serde(default, deny_unknown_fields)]
 pub struct DecisionWeights {
     pub distance_pow: f32,
     pub pheromone_pow: f32,
     pub visited_pow: f32,
+    /// name of the [`routing::RoutingPolicy`] used to pick the next hop
+    pub policy: String,
+    pub fallback: FallbackPolicy,
 }
 
 impl Default for DecisionWeights {
@@ -104,21 +381,60 @@ impl Default for DecisionWeights {
             distance_pow: DISTANCE_POW,
             pheromone_pow: PHEROMONE_POW,
             visited_pow: VISITED_POW,
+            policy: routing::DEFAULT_ROUTING_POLICY.to_string(),
+            fallback: FallbackPolicy::default(),
+        }
+    }
+}
+
+/// How an ant picks its next hop when the decision weights can't be sampled from,
+/// i.e. when they're all zero or one of them overflowed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Reflect, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FallbackPolicy {
+    /// any neighbor, all equally likely
+    #[default]
+    Uniform,
+    /// the closest neighbor
+    Nearest,
+    /// stay in the nest, the request fails once it's over its ttl
+    Wait,
+}
+
+impl FallbackPolicy {
+    /// one of `candidates`, the nests that could be hopped to from `from`
+    pub fn choose(
+        &self,
+        from: Vec2,
+        candidates: &[(NestId, Vec2)],
+        rng: &mut impl Rng,
+    ) -> Option<NestId> {
+        match self {
+            FallbackPolicy::Uniform => candidates.choose(rng).map(|(id, _)| *id),
+            FallbackPolicy::Nearest => candidates
+                .iter()
+                .min_by(|(_, a), (_, b)| from.distance(*a).total_cmp(&from.distance(*b)))
+                .map(|(id, _)| *id),
+            FallbackPolicy::Wait => None,
         }
     }
 }
 
 const TRAIL_PHEROMONE_STEP: f32 = 0.10;
-const TRAIL_PHEROMONE_FADE_RATE: f32 = 0.001;
-const NEST_PHEROMONE_FADE_SPEED: f32 = 0.03;
+// per simulated second, 0.001 and 0.03 per tick at 60 ticks a second
+const TRAIL_PHEROMONE_FADE_RATE: f32 = 0.06;
+const NEST_PHEROMONE_FADE_SPEED: f32 = 1.8;
 const NEST_PHEROMONE_STEP: f32 = 0.1;
 
-#[derive(Debug, Clone, Resource, Reflect)]
+#[derive(Debug, Clone, PartialEq, Resource, Reflect, Serialize, Deserialize)]
 #[reflect(Resource)]
+#[serde(default, deny_unknown_fields)]
 pub struct PheromoneParams {
     pub trail_step: f32,
     pub nest_step: f32,
+    /// strength lost per simulated second
     pub trail_fade_rate: f32,
+    /// strength lost per simulated second
     pub nest_fade_rate: f32,
 }
 
@@ -1,8 +1,8 @@
-use std::f32::consts::PI;
+use std::{f32::consts::PI, fmt, ops::Index};
 
 use crate::{
-    Colors, HexagonMesh, NumAnts, BORDER_PADDING, FOOD_HEIGHT, FOOD_SIZE_V3, MAX_ANTS,
-    NEST_FOOD_REQUEST_PROB, NEST_HEIGHT, NEST_SIZE, NUM_NESTS,
+    clock::SimClock, ArenaSettings, BoundingBox, Colors, HexagonMesh, NumAnts, SimRng, FOOD_HEIGHT,
+    FOOD_SIZE_V3, NEST_HEIGHT, NEST_SIZE,
 };
 
 use bevy::{
@@ -16,101 +16,160 @@ use bevy::{
 #[allow(unused_imports)]
 use rand::seq::SliceRandom;
 
-use rand::{
-    seq::IteratorRandom,
-    Rng,
+use rand::{seq::IteratorRandom, Rng};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    ant,
+    dot::EdgeTraffic,
+    events::{AntVisitedNest, DeliveryCompleted, RequestIssued, TargetReached},
+    food::Food,
+    import::ImportedNetwork,
+    pheromones::Pheromone,
+    topology::{Topology, TopologyParams},
+    NetworkParams, PheromoneParams,
 };
 
-use super::{ant, food::Food, PheromoneParams};
+/// Identity of a nest, used for routing and pheromones.
+/// Which color a nest is drawn with is up to [`Colors`], so any number of nests can share one.
+#[derive(
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Reflect,
+    FromReflect,
+    Serialize,
+    Deserialize,
+)]
+pub struct NestId(pub usize);
+
+impl fmt::Display for NestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
 
 #[derive(Debug, Component, Clone)]
 pub struct Nest {
-    pub color: usize,
-    pub color_weights: Vec<f32>,
+    pub id: NestId,
+    /// pheromone strength towards each nest, indexed by nest id
+    pub weights: Vec<f32>,
     pub loc: Vec2,
+    /// chance of requesting food every tick, instead of `NetworkParams::food_request_prob`
+    pub request_prob: Option<f32>,
 }
 
 impl Nest {
-    pub fn new(color: usize, loc: Vec2) -> Nest {
-        let mut color_weights = Vec::with_capacity(NUM_NESTS);
-        for _i in 0..NUM_NESTS {
-            color_weights.push(0.0);
-        }
+    pub fn new(id: NestId, loc: Vec2, num_nests: usize) -> Nest {
         Self {
-            color,
+            id,
             loc,
-            color_weights,
+            weights: vec![0.0; num_nests],
+            request_prob: None,
         }
     }
     #[inline]
-    pub fn step_pheromone(&mut self, color: usize, step: f32) {
-        let mut weight = self.color_weights[color];
+    pub fn step_pheromone(&mut self, nest: NestId, step: f32) {
+        let mut weight = self.weights[nest.0];
         weight += step;
         weight = weight.min(1.0);
-        self.color_weights[color] = weight;
+        self.weights[nest.0] = weight;
     }
     // TODO: pheromone component
     #[inline]
-    pub fn step_pheromones(&mut self, target_color: usize, parent_color: usize, step: f32) {
-        self.step_pheromone(target_color, step);
-        self.step_pheromone(parent_color, step);
+    pub fn step_pheromones(&mut self, target: NestId, parent: NestId, step: f32) {
+        self.step_pheromone(target, step);
+        self.step_pheromone(parent, step);
     }
 
     pub fn fade(&mut self, rate: f32) {
-        for w in self.color_weights.iter_mut() {
+        for w in self.weights.iter_mut() {
             *w -= rate;
             *w = w.max(0.0);
         }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn food_request_system(
     mut commands: Commands,
     query: Query<(&Nest, &Transform)>,
     ant_texture: Res<ant::AntTexture>,
+    mut pool: ResMut<ant::AntPool>,
     mut num_ants: ResMut<NumAnts>,
-    colors: Res<Colors>,
+    nest_ids: Res<NestIndex>,
+    mut rng: ResMut<SimRng>,
+    params: Res<NetworkParams>,
+    clock: Res<SimClock>,
+    mut issued: EventWriter<RequestIssued>,
 ) {
-    let mut rng = rand::thread_rng();
-    for (nest, transform) in &query {
+    // walk the nests in id order rather than query order so requests are reproducible
+    for &nest_id in &nest_ids.nests {
+        let (nest, transform) = query
+            .get(nest_id)
+            .expect("nest index should only contain existing nests");
         // let color = nest.color;
         // PERF: Bernoulli distribution resource will be more efficien
+        let request_prob = nest.request_prob.unwrap_or(params.food_request_prob);
         let should_ask_for_food: bool =
-            num_ants.0 != MAX_ANTS && rng.gen_bool(NEST_FOOD_REQUEST_PROB as f64);
+            num_ants.0 < params.max_ants && rng.gen_bool(request_prob as f64);
         if should_ask_for_food {
-            let target_color = colors
-                .color_ids
-                .iter()
-                .filter(|c| **c != nest.color)
-                .choose(&mut rng)
-                .unwrap();
-            commands.spawn(ant::AntBundle::new(
+            // a lone nest has nothing to ask for
+            let target = match nest_ids.ids().filter(|&id| id != nest.id).choose(&mut *rng) {
+                Some(target) => target,
+                None => continue,
+            };
+            let ant = pool.dispatch(
+                &mut commands,
                 transform,
-                *target_color,
-                nest.color,
+                target,
+                nest.id,
                 &ant_texture,
-            ));
-            log::info!(
-                "generated ant: nest {:?} target: {:?}",
-                nest.color,
-                target_color
+                params.num_nests,
+                &mut *rng,
             );
             num_ants.0 += 1;
+            issued.send(RequestIssued {
+                ant,
+                origin: nest.id,
+                target,
+                tick: clock.tick,
+            });
         }
     }
 }
 
+/// entity of each nest, indexed by nest id
 #[derive(Resource)]
-pub struct NestColors {
+pub struct NestIndex {
     pub nests: Vec<Entity>,
 }
 
+impl NestIndex {
+    pub fn ids(&self) -> impl Iterator<Item = NestId> {
+        (0..self.nests.len()).map(NestId)
+    }
+}
+
+impl Index<NestId> for NestIndex {
+    type Output = Entity;
+    fn index(&self, id: NestId) -> &Self::Output {
+        &self.nests[id.0]
+    }
+}
+
 // http://blog.marmakoide.org/?p=1
 fn gen_fib_coords(num_points: usize, max_r: f32) -> Vec<Vec2> {
     let golden_angle: f32 = PI * (3.0 - 5.0_f32.sqrt());
     let mut pnts = vec![Vec2::ZERO; num_points];
     let num_points_f = num_points as f32;
-    for (i,v) in pnts.iter_mut().enumerate() {
+    for (i, v) in pnts.iter_mut().enumerate() {
         let i_f = i as f32;
         let theta = (i_f) * golden_angle;
         let r = (i_f.sqrt() / num_points_f.sqrt()) * max_r;
@@ -134,25 +193,58 @@ fn gen_hex_coords(w: u32, h: u32) -> Vec<Vec2> {
     coords
 }
 
+/// Nest positions inside the arena, popped off the back in id order.
+/// Imported positions are used as long as there are as many nests as were imported.
+fn nest_coords(
+    num_nests: usize,
+    bounding_box: &BoundingBox,
+    border_padding: f32,
+    imported: Option<&ImportedNetwork>,
+) -> Vec<Vec2> {
+    let imported = imported
+        .filter(|network| network.nests.len() == num_nests)
+        .and_then(|network| network.locs(bounding_box, border_padding));
+    if let Some(mut locs) = imported {
+        locs.reverse();
+        return locs;
+    }
+    let bounds = bounding_box.size() - 2. * border_padding;
+    gen_fib_coords(num_nests, bounds.min_element() / 2.0)
+}
+
+pub(super) fn nest_bundle(nest: Nest, colors: &Colors, meshes: &mut Assets<Mesh>) -> impl Bundle {
+    let sprite_size = Vec3::new(NEST_SIZE, NEST_SIZE, 0.);
+    let nest_loc = nest.loc.extend(NEST_HEIGHT as f32);
+    (
+        MaterialMesh2dBundle {
+            mesh: meshes.add(shape::Circle::default().into()).into(),
+            material: colors.handle_of(nest.id).clone(),
+            transform: Transform::from_translation(nest_loc).with_scale(sprite_size),
+            ..default()
+        },
+        nest,
+    )
+}
+
+/// Spawns the imported network if there is one, otherwise lays out the nests
+/// and generates the topology.
+#[allow(clippy::too_many_arguments)]
 pub fn spawn_nests(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
-    colors: Res<Colors>,
-    windows: Res<Windows>,
+    mut colors: ResMut<Colors>,
+    bounding_box: Res<BoundingBox>,
+    params: Res<NetworkParams>,
+    topology_params: Res<TopologyParams>,
+    mut rng: ResMut<SimRng>,
+    imported: Option<Res<ImportedNetwork>>,
 ) {
-    let window = windows.primary();
-    let bounds = Vec2 {
-        x: window.width(),
-        y: window.height(),
-    } - 2. * BORDER_PADDING;
-    let sprite_size = Vec3::new(NEST_SIZE, NEST_SIZE, 0.);
-    let mut nests = Vec::with_capacity(NUM_NESTS);
-    for i in 0..NUM_NESTS {
-        // temp value
-        let e = Entity::from_raw(i as u32);
-        nests.push(e);
-    }
+    let imported = imported
+        .as_deref()
+        .filter(|network| network.nests.len() == params.num_nests);
+    let mut nests = Vec::with_capacity(params.num_nests);
+    let mut locs = Vec::with_capacity(params.num_nests);
 
     // hex:
     // let rng = rand::thread_rng();
@@ -168,27 +260,145 @@ pub fn spawn_nests(
     // let mut coords = gen_fib_coords(100, bounds.min_element()/2.0);
     // coords.as_mut_slice().shuffle(&mut rng);
 
-    let mut coords = gen_fib_coords(NUM_NESTS, bounds.min_element() / 2.0);
+    let mut coords = nest_coords(
+        params.num_nests,
+        &bounding_box,
+        params.border_padding,
+        imported,
+    );
+
+    for id in (0..params.num_nests).map(NestId) {
+        let c = coords
+            .pop()
+            .expect("num_nests should always be > num hex coords");
+        locs.push(c);
+        let mut nest = Nest::new(id, c, params.num_nests);
+        if let Some(imported) = imported.map(|network| &network.nests[id.0]) {
+            nest.request_prob = imported.request_prob;
+            if let Some(color) = imported.color {
+                colors.set_color(id, color, &mut materials);
+            }
+        }
+        let nest = commands.spawn(nest_bundle(nest, &colors, &mut meshes)).id();
+        nests.push(nest);
+    }
+    commands.insert_resource(NestIndex { nests });
+    let topology = match imported {
+        Some(network) => network.topology(),
+        None => topology_params.generate(&locs, &mut *rng),
+    };
+    commands.insert_resource(topology);
+}
+
+/// Spawns nests when `NetworkParams::num_nests` is raised, i.e. from the inspector.
+/// Every per nest vector is grown to match, the existing nests are laid out again to make
+/// room and the topology is generated again with the new nests.
+/// Nests can't be removed while running, lowering the count is undone, and an
+/// [`ImportedNetwork`] keeps the nests of its file.
+#[allow(clippy::too_many_arguments)]
+pub fn add_nests(
+    mut commands: Commands,
+    mut params: ResMut<NetworkParams>,
+    mut colors: ResMut<Colors>,
+    mut nest_ids: ResMut<NestIndex>,
+    mut topology: ResMut<Topology>,
+    topology_params: Res<TopologyParams>,
+    mut rng: ResMut<SimRng>,
+    mut nests: Query<(&mut Nest, &mut Transform)>,
+    mut pheromones: Query<&mut Pheromone>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    bounding_box: Res<BoundingBox>,
+    imported: Option<Res<ImportedNetwork>>,
+) {
+    let num_nests = params.num_nests;
+    let old_num_nests = nest_ids.nests.len();
+    if !params.is_changed() || num_nests == old_num_nests {
+        return;
+    }
+    if num_nests < old_num_nests {
+        log::warn!(
+            "can't remove nests while running, keeping {} nests",
+            old_num_nests
+        );
+        params.num_nests = old_num_nests;
+        return;
+    }
+    if imported.is_some() {
+        log::warn!(
+            "can't add nests to an imported network, keeping {} nests",
+            old_num_nests
+        );
+        params.num_nests = old_num_nests;
+        return;
+    }
+
+    colors.resize(num_nests);
+    for mut pheromone in &mut pheromones {
+        pheromone.weights.resize(num_nests, 0.0);
+    }
 
-    for (color, color_id) in colors.iter() {
-        let c = &coords
+    let mut coords = nest_coords(num_nests, &bounding_box, params.border_padding, None);
+    let mut locs = Vec::with_capacity(num_nests);
+    for &nest_id in &nest_ids.nests {
+        let (mut nest, mut transform) = nests
+            .get_mut(nest_id)
+            .expect("nest index should only contain existing nests");
+        let c = coords
             .pop()
-            .expect("NUM_NESTS should always be > num hex coords");
-        let nest_loc = Vec3::new(c.x, c.y, NEST_HEIGHT as f32);
-        let id = commands
-            .spawn((
-                MaterialMesh2dBundle {
-                    mesh: meshes.add(shape::Circle::default().into()).into(),
-                    material: materials.add(ColorMaterial::from(color)),
-                    transform: Transform::from_translation(nest_loc).with_scale(sprite_size),
-                    ..default()
-                },
-                Nest::new(color_id, nest_loc.truncate()),
+            .expect("there should be a coordinate for every nest");
+        nest.weights.resize(num_nests, 0.0);
+        locs.push(c);
+        nest.loc = c;
+        transform.translation.x = c.x;
+        transform.translation.y = c.y;
+    }
+    for id in (old_num_nests..num_nests).map(NestId) {
+        let c = coords
+            .pop()
+            .expect("there should be a coordinate for every nest");
+        locs.push(c);
+        let nest = commands
+            .spawn(nest_bundle(
+                Nest::new(id, c, num_nests),
+                &colors,
+                &mut meshes,
             ))
             .id();
-        nests[color_id] = id;
+        nest_ids.nests.push(nest);
+    }
+    *topology = topology_params.generate(&locs, &mut *rng);
+    log::info!("added {} nests", num_nests - old_num_nests);
+}
+
+/// moves every nest to where it would have been spawned in the resized arena
+pub fn relayout_nests(
+    bounding_box: Res<BoundingBox>,
+    settings: Res<ArenaSettings>,
+    nest_ids: Res<NestIndex>,
+    params: Res<NetworkParams>,
+    imported: Option<Res<ImportedNetwork>>,
+    mut nests: Query<(&mut Nest, &mut Transform)>,
+) {
+    if !settings.relayout_nests || !bounding_box.is_changed() || bounding_box.is_added() {
+        return;
+    }
+    let mut coords = nest_coords(
+        nest_ids.nests.len(),
+        &bounding_box,
+        params.border_padding,
+        imported.as_deref(),
+    );
+    for &nest_id in &nest_ids.nests {
+        let (mut nest, mut transform) = nests
+            .get_mut(nest_id)
+            .expect("nest index should only contain existing nests");
+        let c = coords
+            .pop()
+            .expect("there should be a coordinate for every nest");
+        nest.loc = c;
+        transform.translation.x = c.x;
+        transform.translation.y = c.y;
     }
-    commands.insert_resource(NestColors { nests });
 }
 
 #[inline]
@@ -198,9 +408,13 @@ fn pos_size(t: Transform) -> (Vec3, Vec2) {
     (pos, size)
 }
 
-pub fn fade_nest_network_pheremones(mut nests: Query<&mut Nest>, params: Res<PheromoneParams>) {
+pub fn fade_nest_network_pheremones(
+    mut nests: Query<&mut Nest>,
+    params: Res<PheromoneParams>,
+    clock: Res<SimClock>,
+) {
     for mut nest in &mut nests {
-        nest.fade(params.nest_fade_rate);
+        nest.fade(params.nest_fade_rate * clock.tick_length);
     }
 }
 
@@ -210,82 +424,123 @@ const FOOD_OFFSET: Vec3 = Vec3 {
     z: FOOD_HEIGHT as f32,
 };
 
+/// the data an ant carries back from `nest`, spawned as a child of the ant
+pub(super) fn food_bundle(nest: NestId, colors: &Colors, hex_mesh: &HexagonMesh) -> impl Bundle {
+    (
+        MaterialMesh2dBundle {
+            mesh: hex_mesh.clone_weak().into(),
+            material: colors.handle_of(nest).clone_weak(),
+            transform: Transform::from_translation(FOOD_OFFSET).with_scale(FOOD_SIZE_V3),
+            visibility: Visibility { is_visible: true },
+            ..default()
+        },
+        Food::new(nest),
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn ant_nest_network_interactions(
     mut commands: Commands,
     colors: Res<Colors>,
-    mut ants: Query<(Entity, &mut ant::Ant, &Transform)>,
+    mut ants: Query<(Entity, &mut ant::Ant, &Transform), Without<ant::Pooled>>,
     mut nests: Query<(&Nest, &Transform)>,
     hex_mesh: Res<HexagonMesh>,
+    mut pool: ResMut<ant::AntPool>,
+    mut num_ants: ResMut<NumAnts>,
+    clock: Res<SimClock>,
+    mut traffic: ResMut<EdgeTraffic>,
+    mut visited: EventWriter<AntVisitedNest>,
+    mut reached: EventWriter<TargetReached>,
+    mut completed: EventWriter<DeliveryCompleted>,
 ) {
+    let mut delivered = Vec::new();
     for (nest, nest_transform) in &mut nests {
         for (ant_id, mut ant, ant_transform) in &mut ants {
             let (nest_pos, nest_size) = pos_size(*nest_transform);
             let (ant_pos, ant_size) = pos_size(*ant_transform);
 
-            // skip ants we already updated
-            if nest.color == ant.prev_nest() {
+            // only the nest the ant is hopping to counts, and only until it's been handled
+            if ant.retired || ant.next_nest != Some(nest.id) {
                 continue;
             }
             let collision = collide(nest_pos, nest_size * 2., ant_pos, ant_size);
             match collision {
                 Some(_) => {
-                    if ant.target_color == nest.color {
+                    ant.next_nest = None;
+                    traffic.record(ant.prev_nest(), nest.id);
+                    if ant.target == nest.id {
                         if !ant.carrying_food {
                             // commands.entity(ant_id).add_child(food_id);
                             commands.entity(ant_id).with_children(|builder| {
-                                builder.spawn((
-                                    MaterialMesh2dBundle {
-                                        mesh: hex_mesh.clone_weak().into(),
-                                        material: colors.color_handles[nest.color].clone_weak(),
-                                        transform: Transform::from_translation(FOOD_OFFSET)
-                                            .with_scale(FOOD_SIZE_V3),
-                                        visibility: Visibility { is_visible: true },
-                                        ..default()
-                                    },
-                                    Food::new(nest.color),
-                                ));
+                                builder.spawn(food_bundle(nest.id, &colors, &hex_mesh));
+                            });
+                            reached.send(TargetReached {
+                                ant: ant_id,
+                                origin: ant.parent,
+                                target: nest.id,
+                                hops: ant.hops,
+                                distance: ant.distance,
+                                tick: clock.tick,
+                                elapsed: ant.age,
                             });
-                            ant.target_color = ant.parent_color;
+                            ant.target = ant.parent;
                             // not parent but this will cause to and from pheromone trails
                             // to be set on the way to target and on the way back
-                            ant.parent_color = nest.color;
+                            ant.parent = nest.id;
                             ant.carrying_food = true;
-                            log::info!(
-                                "Ant reached target nest {} after {} steps",
-                                nest.color,
-                                ant.steps
-                            );
+                            // head back along the edges from here
+                            ant.visit_nest(nest.id);
                             ant.wipe_mem()
                         } else {
-                            // despawn food
-                            commands.entity(ant_id).despawn_descendants();
-                            ant.target_color = ant.parent_color;
-                            ant.parent_color = nest.color;
-                            ant.carrying_food = false;
-                            log::info!(
-                                "Ant reached parent nest {} after {} steps",
-                                nest.color,
-                                ant.steps
-                            );
-                            ant.wipe_mem()
+                            // request fulfilled, free up the slot for another one
+                            delivered.push(ant_id);
                         }
                         // let orientation = ant.orientation + PI;
                         // ant.set_orientation(orientation);
                         // ant.set_target_orientation(orientation);
                         // ant.turn_around = true;
                     } else {
-                        // log::info!(
-                        //     "ant heading to {} taking pit stop at {}",
-                        //     ant.target_color,
-                        //     nest.color
-                        // );
                         // figure out jump point
                         //
-                        ant.visit_nest(nest.color);
+                        let (origin, target) = ant.request();
+                        visited.send(AntVisitedNest {
+                            ant: ant_id,
+                            nest: nest.id,
+                            from: ant.prev_nest(),
+                            origin,
+                            target,
+                            hops: ant.hops,
+                            tick: clock.tick,
+                            elapsed: ant.age,
+                        });
+                        ant.visit_nest(nest.id);
                     }
                 }
                 None => continue,
             }
         }
     }
+
+    // retired in entity order, like in `expire_requests`, so the pool is reproducible
+    delivered.sort_unstable();
+    for ant_id in delivered {
+        let (_, mut ant, _) = ants
+            .get_mut(ant_id)
+            .expect("delivered ants should still exist");
+        if !pool.retire(&mut commands, ant_id, &mut ant) {
+            continue;
+        }
+        completed.send(DeliveryCompleted {
+            ant: ant_id,
+            // back at its origin, which it's been heading to since reaching the target
+            origin: ant.target,
+            target: ant.parent,
+            hops: ant.hops,
+            distance: ant.distance,
+            tick: clock.tick,
+            elapsed: ant.age,
+        });
+        ant.carrying_food = false;
+        num_ants.0 = num_ants.0.saturating_sub(1);
+    }
 }
@@ -1,47 +1,56 @@
 use std::ops::{Index, IndexMut};
 
-use crate::{Colors, HexagonMesh, BOARD_HEIGHT, NUM_NESTS};
+use crate::{clock::SimClock, BoundingBox, Colors, HexagonMesh, BOARD_HEIGHT};
 
 #[allow(unused_imports)]
 use bevy::log;
 
 use bevy::{prelude::*, sprite::MaterialMesh2dBundle};
 
-use super::{ant::Ant, PheromoneParams};
+use super::{
+    ant::{Ant, Pooled},
+    nest::{NestId, NestIndex},
+    NetworkParams, PheromoneParams,
+};
 
-const PHEROMONE_GRANULARITY: u32 = 8;
+pub const PHEROMONE_GRANULARITY: u32 = 8;
 const PHEROMONE_GRANULARITY_F: f32 = PHEROMONE_GRANULARITY as f32;
 const PHEROMONE_SCALE: f32 = 4.0;
 // const PHEROMONE_FADE_PERCENTAGE: f32 = 1.0 - PHEROMONE_FADE_RATE;
 
 #[derive(Debug, Component)]
 pub struct Pheromone {
-    // TODO: NUM_COLORS not num nests
+    /// trail strength towards each nest, indexed by nest id
     pub weights: Vec<f32>,
     loc: UVec2,
 }
 
 impl Pheromone {
-    pub fn new(_num_colors: usize, loc: UVec2) -> Self {
-        let weights = vec![0.; NUM_NESTS];
+    pub fn new(num_nests: usize, loc: UVec2) -> Self {
+        let weights = vec![0.; num_nests];
         Self { weights, loc }
     }
-    pub fn add_trail(&mut self, color: usize, step: f32) {
-        self.weights[color] += step;
+    /// the cell of the pheromone grid it's in
+    pub fn loc(&self) -> UVec2 {
+        self.loc
+    }
+
+    pub fn add_trail(&mut self, nest: NestId, step: f32) {
+        self.weights[nest.0] += step;
     }
 
     pub fn is_empty(&self) -> bool {
         return self.weights.iter().sum::<f32>() <= f32::EPSILON;
     }
 
-    pub fn most_prominent(&self) -> usize {
-        return self
+    pub fn most_prominent(&self) -> NestId {
+        let (nest, _) = self
             .weights
             .iter()
             .enumerate()
             .max_by_key(|tup| (*tup.1 * 100.0) as usize)
-            .expect("pheromone weights shouldn't be empty")
-            .0;
+            .expect("pheromone weights shouldn't be empty");
+        NestId(nest)
     }
     pub fn fade(&mut self, rate: f32) {
         for w in &mut self.weights {
@@ -73,24 +82,26 @@ pub struct PheromoneManager {
     grid_dims: UVec2,
     child_ids: PheromoneGrid,
     pub win: UVec2,
+    /// side length of a cell
+    pub granularity: u32,
 
     // #[inspectable(ignore)]
-    pub color_queue: Vec<(usize, Entity)>,
+    pub color_queue: Vec<(NestId, Entity)>,
 }
 
 impl PheromoneManager {
-    fn get_grid_dims_from_window_size(win: UVec2) -> UVec2 {
-        win / PHEROMONE_GRANULARITY
+    fn get_grid_dims_from_window_size(win: UVec2, granularity: u32) -> UVec2 {
+        win / granularity
     }
 
-    pub fn new(width: f32, height: f32) -> Self {
+    pub fn new(width: f32, height: f32, granularity: u32) -> Self {
         let width = width as u32;
         let height = height as u32;
         let win = UVec2 {
             x: width,
             y: height,
         };
-        let grid_dims = Self::get_grid_dims_from_window_size(win);
+        let grid_dims = Self::get_grid_dims_from_window_size(win, granularity);
         let grid_size = (grid_dims.x * grid_dims.y) as usize;
         let child_ids = PheromoneGrid(vec![None; grid_size]);
         // for i in 0..grid_size {
@@ -102,6 +113,7 @@ impl PheromoneManager {
             grid_dims,
             child_ids,
             win,
+            granularity,
             color_queue: Vec::new(),
         }
     }
@@ -118,8 +130,32 @@ impl PheromoneManager {
 
     pub fn cell_containing(&self, loc: Vec2) -> UVec2 {
         let relative_loc = (loc + self.win.as_vec2() / 2.0).as_uvec2();
-        //.clamp(UVec2::ZERO, self.grid_dims);
-        relative_loc / PHEROMONE_GRANULARITY
+        // ants outside the arena (i.e. just after it shrinks) use the closest cell
+        (relative_loc / self.granularity).min(self.grid_dims.max(UVec2::ONE) - UVec2::ONE)
+    }
+
+    /// position of the corner of a grid cell relative to the manager
+    pub fn cell_offset(&self, cell: UVec2) -> Vec2 {
+        (cell * self.granularity).as_vec2()
+    }
+
+    /// world position of the center of a grid cell
+    fn cell_center(&self, cell: UVec2) -> Vec2 {
+        self.cell_offset(cell) + self.granularity as f32 / 2.0 - self.win.as_vec2() / 2.0
+    }
+
+    /// whether a cell is inside the grid
+    pub fn contains(&self, cell: UVec2) -> bool {
+        cell.cmplt(self.grid_dims).all()
+    }
+
+    pub fn num_cells(&self) -> usize {
+        self.child_ids.len()
+    }
+
+    /// cells with a pheromone in them
+    pub fn filled_cells(&self) -> usize {
+        self.child_ids.iter().filter(|c| c.is_some()).count()
     }
 
     pub fn id_of_pheromone_at(&self, ant_loc: Vec2) -> Option<Entity> {
@@ -255,12 +291,12 @@ impl IndexMut<UVec2> for PheromoneManager {
 
 pub fn create_pheromone_manager(
     mut commands: Commands,
-    windows: Res<Windows>,
+    bounding_box: Res<BoundingBox>,
+    params: Res<NetworkParams>,
     // mut materials: ResMut<Assets<ColorMaterial>>,
 ) {
-    let window = windows.primary();
-    let (height, width) = (window.height(), window.width());
-    let manager = PheromoneManager::new(width, height);
+    let (height, width) = (bounding_box.h, bounding_box.w);
+    let manager = PheromoneManager::new(width, height, params.pheromone_granularity);
     let mut entity_commands = commands.spawn((
         SpatialBundle {
             transform: Transform::from_xyz(-(width / 2.0), -(height / 2.0), BOARD_HEIGHT as f32),
@@ -299,19 +335,87 @@ pub fn create_pheromone_manager(
     entity_commands.insert(manager);
 }
 
+/// Rebuilds the grid when the arena changes size.
+/// Pheromones keep their place in the world, ones that end up outside the arena are removed
+/// and ones that end up sharing a cell are merged.
+pub fn resize_pheromone_grid(
+    mut commands: Commands,
+    bounding_box: Res<BoundingBox>,
+    params: Res<NetworkParams>,
+    mut pheromone_manager: Query<(&mut PheromoneManager, &mut Transform), Without<Pheromone>>,
+    mut pheromones: Query<(&mut Pheromone, &mut Transform)>,
+) {
+    if !bounding_box.is_changed() || bounding_box.is_added() {
+        return;
+    }
+    let (mut manager, mut manager_transform) = pheromone_manager
+        .get_single_mut()
+        .expect("there should be pheromones");
+    let mut resized =
+        PheromoneManager::new(bounding_box.w, bounding_box.h, params.pheromone_granularity);
+    if resized.win == manager.win && resized.granularity == manager.granularity {
+        return;
+    }
+    let half_arena = resized.win.as_vec2() / 2.0;
+
+    for pheromone_id in manager.child_ids.iter().flatten().copied() {
+        let old_loc = pheromones
+            .get(pheromone_id)
+            .expect("pheromone manager grid should only contain exisiting entities")
+            .0
+            .loc;
+        let world_loc = manager.cell_center(old_loc);
+        if world_loc.abs().cmpge(half_arena).any() {
+            commands.entity(pheromone_id).despawn_recursive();
+            continue;
+        }
+        let new_loc = resized.cell_containing(world_loc);
+        match resized[new_loc] {
+            Some(existing_id) => {
+                let [(mut existing, _), (pheromone, _)] = pheromones
+                    .get_many_mut([existing_id, pheromone_id])
+                    .expect("pheromones in the grid should exist");
+                for (w, other) in existing.weights.iter_mut().zip(&pheromone.weights) {
+                    *w += other;
+                }
+                commands.entity(pheromone_id).despawn_recursive();
+            }
+            None => {
+                let (mut pheromone, mut transform) = pheromones
+                    .get_mut(pheromone_id)
+                    .expect("pheromones in the grid should exist");
+                pheromone.loc = new_loc;
+                let scaled_loc = resized.cell_offset(new_loc);
+                transform.translation.x = scaled_loc.x;
+                transform.translation.y = scaled_loc.y;
+                resized[new_loc] = Some(pheromone_id);
+            }
+        }
+    }
+
+    // trails waiting to be laid in removed pheromones are dropped
+    resized.color_queue = manager
+        .color_queue
+        .drain(..)
+        .filter(|(_, id)| resized.child_ids.contains(&Some(*id)))
+        .collect();
+    *manager = resized;
+    manager_transform.translation.x = -(bounding_box.w / 2.0);
+    manager_transform.translation.y = -(bounding_box.h / 2.0);
+}
+
 pub fn fade_pheromones(
     mut commands: Commands,
     mut pheromone_manager: Query<&mut PheromoneManager>,
     mut pheromones: Query<(Entity, &mut Pheromone, &mut Visibility)>,
     params: Res<PheromoneParams>,
-    time: Res<Time>,
+    clock: Res<SimClock>,
 ) {
     let pheromone_manager = &mut pheromone_manager
         .get_single_mut()
         .expect("there should be pheromones");
-    let rate = params.trail_fade_rate * (1.0 + time.delta_seconds());
     for (id, mut pheromone, mut visibility) in &mut pheromones {
-        pheromone.fade(rate);
+        pheromone.fade(params.trail_fade_rate * clock.tick_length);
         visibility.is_visible = !pheromone.is_empty();
         if !visibility.is_visible {
             // will prevent this pheromone from being looped over until another ant steps on it
@@ -326,11 +430,33 @@ pub fn fade_pheromones(
     }
 }
 
+/// spawned as a child of the pheromone manager, in its grid cell
+pub(super) fn pheromone_bundle(
+    pheromone: Pheromone,
+    color: &Handle<ColorMaterial>,
+    manager: &PheromoneManager,
+    hex_mesh: &HexagonMesh,
+) -> impl Bundle {
+    let scaled_loc = manager.cell_offset(pheromone.loc);
+    (
+        MaterialMesh2dBundle {
+            // mesh: meshes.add(shape::Circle::default().into()).into(),
+            mesh: hex_mesh.clone_weak().into(),
+            material: color.clone_weak(),
+            transform: Transform::from_xyz(scaled_loc.x, scaled_loc.y, BOARD_HEIGHT as f32)
+                .with_scale(Vec3::splat(PHEROMONE_SCALE)),
+            ..default()
+        },
+        pheromone,
+    )
+}
+
 pub fn create_required_pheromones(
     mut commands: Commands,
-    ants: Query<(&Ant, &Transform)>,
+    ants: Query<(&Ant, &Transform), Without<Pooled>>,
     mut pheromone_manager: Query<(Entity, &mut PheromoneManager)>,
     colors: Res<Colors>,
+    nest_ids: Res<NestIndex>,
     pher_params: Res<PheromoneParams>,
     hex_mesh: Res<HexagonMesh>,
 ) {
@@ -344,44 +470,32 @@ pub fn create_required_pheromones(
         let pheromone_loc = pheromone_manager.cell_containing(ant_loc);
         let opt_pheromone_tile = pheromone_manager[pheromone_loc];
 
-        let trail_color = ant.parent_color;
+        let trail_nest = ant.parent;
 
         match opt_pheromone_tile {
             Some(pheromone_id) => {
                 pheromone_manager
                     .color_queue
-                    .push((trail_color, pheromone_id));
+                    .push((trail_nest, pheromone_id));
             }
             None => {
-                let mut pheromone = Pheromone::new(colors.colors.len(), pheromone_loc);
+                let mut pheromone = Pheromone::new(nest_ids.nests.len(), pheromone_loc);
 
-                pheromone.add_trail(trail_color, pher_params.trail_step);
+                pheromone.add_trail(trail_nest, pher_params.trail_step);
 
-                let scaled_loc = (pheromone_loc * PHEROMONE_GRANULARITY).as_vec2();
+                let bundle = pheromone_bundle(
+                    pheromone,
+                    colors.handle_of(trail_nest),
+                    &pheromone_manager,
+                    &hex_mesh,
+                );
                 commands.entity(manager_id).with_children(|builder| {
-                    let pheromone_id = builder
-                        .spawn((
-                            MaterialMesh2dBundle {
-                                // mesh: meshes.add(shape::Circle::default().into()).into(),
-                                mesh: hex_mesh.clone_weak().into(),
-                                //FIXME: no color here
-                                material: colors.color_handles[trail_color].clone_weak(),
-                                transform: Transform::from_xyz(
-                                    scaled_loc.x,
-                                    scaled_loc.y,
-                                    BOARD_HEIGHT as f32,
-                                )
-                                .with_scale(Vec3::splat(PHEROMONE_SCALE)),
-                                ..default()
-                            },
-                            pheromone,
-                        ))
-                        .id();
+                    let pheromone_id = builder.spawn(bundle).id();
                     pheromone_manager[pheromone_loc] = Some(pheromone_id);
                     // log::info!("created pheromone {:?} at {:?}", pheromone_id, pheromone_loc);
                     pheromone_manager
                         .color_queue
-                        .push((trail_color, pheromone_id));
+                        .push((trail_nest, pheromone_id));
                 });
             }
         }
@@ -398,17 +512,16 @@ pub fn leave_pheromone_trails(
         .get_single_mut()
         .expect("there should be pheromones");
     // let bounds = pheromone_manager.win;
-    for (trail_color, pheromone_id) in pheromone_manager.color_queue.drain(0..) {
+    for (trail_nest, pheromone_id) in pheromone_manager.color_queue.drain(0..) {
         let (_, mut pheromone, mut color_handle) = pheromones
             .get_mut(pheromone_id)
             .expect("pheromone manager grid should only contain exisiting entities");
 
         // to find our way home
-        pheromone.add_trail(trail_color, pher_params.trail_step);
+        pheromone.add_trail(trail_nest, pher_params.trail_step);
 
         // color trail
-        let color_id = pheromone.most_prominent();
-        let cur_color_handle: &Handle<ColorMaterial> = &colors.color_handles[color_id];
+        let cur_color_handle: &Handle<ColorMaterial> = colors.handle_of(pheromone.most_prominent());
         if cur_color_handle.id() != color_handle.id() {
             *color_handle = cur_color_handle.clone_weak();
         }
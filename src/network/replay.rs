@@ -0,0 +1,452 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use bevy::{log, prelude::*};
+use iyes_loopless::prelude::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    clock::{PendingSteps, SimClock, TimeScale, SIM_TICK, TIME_SCALES},
+    config::SimConfig,
+    ArenaSettings, BoundingBox, GameMode, GameState,
+};
+
+use super::{
+    import::ImportedNetwork, topology::TopologyParams, DecisionWeights, NetworkParams,
+    PheromoneParams, RequestLimits,
+};
+
+/// simulated seconds `]` skips ahead in a replay
+const SKIP_SECONDS: f32 = 10.;
+
+/// everything a recorded run started from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordingStart {
+    /// always has a seed
+    pub config: SimConfig,
+    pub clock: SimClock,
+    pub arena: BoundingBox,
+    pub arena_settings: ArenaSettings,
+    /// the network the run was started with, if it was imported
+    #[serde(default)]
+    pub network: Option<ImportedNetwork>,
+}
+
+impl RecordingStart {
+    pub fn from_world(world: &World) -> Self {
+        Self {
+            config: SimConfig::from_world(world),
+            clock: world.resource::<SimClock>().clone(),
+            arena: *world.resource::<BoundingBox>(),
+            arena_settings: world.resource::<ArenaSettings>().clone(),
+            network: world.get_resource::<ImportedNetwork>().cloned(),
+        }
+    }
+}
+
+/// Something that was changed from outside the simulation, i.e. from the inspector.
+/// Pausing isn't one, the ticks run the same whether or not they're stepped through.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Change {
+    Network(NetworkParams),
+    DecisionWeights(DecisionWeights),
+    Pheromones(PheromoneParams),
+    Limits(RequestLimits),
+    Topology(TopologyParams),
+    Arena(BoundingBox),
+    ArenaSettings(ArenaSettings),
+    /// [`SimClock::tick_length`], the tick count only ever moves on by itself
+    TickLength(f32),
+}
+
+/// a change and the tick it took effect on
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Intervention {
+    pub tick: u64,
+    pub change: Change,
+}
+
+/// A run that can be played back tick for tick, stored as json lines.
+/// The first line is the [`RecordingStart`] and every other line an [`Intervention`].
+/// Lines are written as they happen, so a run that panics still leaves a full recording.
+#[derive(Debug, Clone)]
+pub struct Recording {
+    pub start: RecordingStart,
+    /// in tick order
+    pub interventions: Vec<Intervention>,
+}
+
+#[derive(Debug)]
+pub enum RecordingError {
+    Io(io::Error),
+    Json {
+        line: usize,
+        error: serde_json::Error,
+    },
+    Empty,
+}
+
+impl fmt::Display for RecordingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordingError::Io(e) => write!(f, "couldn't read recording: {}", e),
+            RecordingError::Json { line, error } => {
+                write!(f, "couldn't parse line {} of recording: {}", line, error)
+            }
+            RecordingError::Empty => write!(f, "recording is empty"),
+        }
+    }
+}
+
+impl std::error::Error for RecordingError {}
+
+impl From<io::Error> for RecordingError {
+    fn from(e: io::Error) -> Self {
+        RecordingError::Io(e)
+    }
+}
+
+fn parse_line<T: DeserializeOwned>(i: usize, line: &str) -> Result<T, RecordingError> {
+    serde_json::from_str(line).map_err(|error| RecordingError::Json { line: i + 1, error })
+}
+
+impl Recording {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, RecordingError> {
+        let contents = fs::read_to_string(path)?;
+        let mut lines = contents
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty());
+
+        let (i, first) = lines.next().ok_or(RecordingError::Empty)?;
+        let start = parse_line(i, first)?;
+        let interventions = lines
+            .map(|(i, line)| parse_line(i, line))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            start,
+            interventions,
+        })
+    }
+}
+
+/// the values interventions are looked for in
+#[derive(Debug, Clone, PartialEq)]
+struct Watched {
+    network: NetworkParams,
+    decision_weights: DecisionWeights,
+    pheromones: PheromoneParams,
+    limits: RequestLimits,
+    topology: TopologyParams,
+    arena: BoundingBox,
+    arena_settings: ArenaSettings,
+    tick_length: f32,
+}
+
+impl Watched {
+    fn changes_since(&self, last: &Watched) -> Vec<Change> {
+        let mut changes = Vec::new();
+        if self.network != last.network {
+            changes.push(Change::Network(self.network.clone()));
+        }
+        if self.decision_weights != last.decision_weights {
+            changes.push(Change::DecisionWeights(self.decision_weights.clone()));
+        }
+        if self.pheromones != last.pheromones {
+            changes.push(Change::Pheromones(self.pheromones.clone()));
+        }
+        if self.limits != last.limits {
+            changes.push(Change::Limits(self.limits.clone()));
+        }
+        if self.topology != last.topology {
+            changes.push(Change::Topology(self.topology.clone()));
+        }
+        if self.arena != last.arena {
+            changes.push(Change::Arena(self.arena));
+        }
+        if self.arena_settings != last.arena_settings {
+            changes.push(Change::ArenaSettings(self.arena_settings.clone()));
+        }
+        if self.tick_length != last.tick_length {
+            changes.push(Change::TickLength(self.tick_length));
+        }
+        changes
+    }
+}
+
+/// writes a [`Recording`] as the simulation runs
+#[derive(Resource)]
+pub struct Recorder {
+    out: BufWriter<File>,
+    last: Watched,
+}
+
+impl Recorder {
+    pub fn create(path: &Path, start: &RecordingStart) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(&mut out, start)?;
+        writeln!(out)?;
+        out.flush()?;
+        let config = &start.config;
+        Ok(Self {
+            out,
+            last: Watched {
+                network: config.network.clone(),
+                decision_weights: config.decision_weights.clone(),
+                pheromones: config.pheromones.clone(),
+                limits: config.limits.clone(),
+                topology: config.topology.clone(),
+                arena: start.arena,
+                arena_settings: start.arena_settings.clone(),
+                tick_length: start.clock.tick_length,
+            },
+        })
+    }
+
+    fn write(&mut self, intervention: &Intervention) -> io::Result<()> {
+        serde_json::to_writer(&mut self.out, intervention)?;
+        writeln!(self.out)?;
+        // there's no telling when the app will stop, or panic
+        self.out.flush()
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn record_interventions(
+    mut recorder: ResMut<Recorder>,
+    clock: Res<SimClock>,
+    network: Res<NetworkParams>,
+    decision_weights: Res<DecisionWeights>,
+    pheromones: Res<PheromoneParams>,
+    limits: Res<RequestLimits>,
+    topology: Res<TopologyParams>,
+    arena: Res<BoundingBox>,
+    arena_settings: Res<ArenaSettings>,
+) {
+    let current = Watched {
+        network: network.clone(),
+        decision_weights: decision_weights.clone(),
+        pheromones: pheromones.clone(),
+        limits: limits.clone(),
+        topology: topology.clone(),
+        arena: *arena,
+        arena_settings: arena_settings.clone(),
+        tick_length: clock.tick_length,
+    };
+    if current == recorder.last {
+        return;
+    }
+    for change in current.changes_since(&recorder.last) {
+        let intervention = Intervention {
+            tick: clock.tick,
+            change,
+        };
+        if let Err(e) = recorder.write(&intervention) {
+            log::error!("couldn't record {:?}: {}", intervention, e);
+        }
+    }
+    recorder.last = current;
+}
+
+/// the interventions of a recording that haven't been made yet
+#[derive(Resource)]
+pub struct Playback {
+    interventions: VecDeque<Intervention>,
+}
+
+impl Playback {
+    fn next_due(&mut self, tick: u64) -> Option<Change> {
+        if self.interventions.front()?.tick > tick {
+            return None;
+        }
+        self.interventions.pop_front().map(|i| i.change)
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn apply_interventions(
+    mut playback: ResMut<Playback>,
+    mut clock: ResMut<SimClock>,
+    mut network: ResMut<NetworkParams>,
+    mut decision_weights: ResMut<DecisionWeights>,
+    mut pheromones: ResMut<PheromoneParams>,
+    mut limits: ResMut<RequestLimits>,
+    mut topology: ResMut<TopologyParams>,
+    mut arena: ResMut<BoundingBox>,
+    mut arena_settings: ResMut<ArenaSettings>,
+) {
+    while let Some(change) = playback.next_due(clock.tick) {
+        log::debug!("replaying {:?} on tick {}", change, clock.tick);
+        match change {
+            Change::Network(n) => *network = n,
+            Change::DecisionWeights(w) => *decision_weights = w,
+            Change::Pheromones(p) => *pheromones = p,
+            Change::Limits(l) => *limits = l,
+            Change::Topology(t) => *topology = t,
+            Change::Arena(a) => *arena = a,
+            // the window never resizes the arena of a replay, every resize was recorded
+            Change::ArenaSettings(s) => {
+                *arena_settings = ArenaSettings {
+                    follow_window: false,
+                    ..s
+                }
+            }
+            Change::TickLength(l) => clock.tick_length = l,
+        }
+    }
+}
+
+/// `]` to skip [`SKIP_SECONDS`] ahead as fast as possible, pausing when it gets there
+pub fn playback_controls(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    clock: Res<SimClock>,
+    mut scale: ResMut<TimeScale>,
+    mut steps: ResMut<PendingSteps>,
+    mut skipping_from: Local<Option<f32>>,
+) {
+    if keys.just_pressed(KeyCode::RBracket) {
+        steps.0 += (SKIP_SECONDS / clock.tick_length).round() as u32;
+        skipping_from.get_or_insert(scale.0);
+        scale.0 = TIME_SCALES[TIME_SCALES.len() - 1];
+        commands.insert_resource(NextState(GameState::Paused));
+    } else if let (Some(previous), 0) = (*skipping_from, steps.0) {
+        scale.0 = previous;
+        *skipping_from = None;
+    }
+}
+
+/// Records the interventions made while running to the [`Recorder`], which has to be
+/// inserted first, or plays back the ones of a recording.
+/// A replay has to be set up with the config, clock and arena of [`RecordingStart`].
+pub enum ReplayPlugin {
+    Record,
+    Playback(Recording),
+}
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        match self {
+            ReplayPlugin::Record => {
+                app.add_fixed_timestep_system_set(
+                    SIM_TICK,
+                    0,
+                    ConditionSet::new()
+                        .run_in_state(GameMode::AntNetwork)
+                        .before("network changes")
+                        .with_system(record_interventions)
+                        .into(),
+                );
+            }
+            ReplayPlugin::Playback(recording) => {
+                app.insert_resource(Playback {
+                    interventions: recording.interventions.iter().cloned().collect(),
+                })
+                .add_fixed_timestep_system_set(
+                    SIM_TICK,
+                    0,
+                    ConditionSet::new()
+                        .run_in_state(GameMode::AntNetwork)
+                        .before("network changes")
+                        .with_system(apply_interventions)
+                        .into(),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{batch::headless_app, network::metrics::Metrics, SimRng};
+
+    use super::*;
+
+    const TICKS: u64 = 900;
+
+    fn run_until(app: &mut App, tick: u64) {
+        while app.world.resource::<SimClock>().tick < tick {
+            app.update();
+        }
+    }
+
+    #[test]
+    fn replay_matches_the_recorded_run() {
+        let path =
+            std::env::temp_dir().join(format!("ant-sim-replay-test-{}.jsonl", std::process::id()));
+        let mut recorded = headless_app(SimConfig {
+            seed: Some(5),
+            ..default()
+        });
+        let start = RecordingStart::from_world(&recorded.world);
+        let recorder = Recorder::create(&path, &start).unwrap();
+        recorded
+            .insert_resource(recorder)
+            .add_plugin(ReplayPlugin::Record);
+        // startup
+        recorded.update();
+
+        run_until(&mut recorded, 200);
+        recorded
+            .world
+            .resource_mut::<DecisionWeights>()
+            .pheromone_pow += 1.;
+        run_until(&mut recorded, 400);
+        recorded.world.resource_mut::<SimClock>().tick_length /= 2.;
+        run_until(&mut recorded, 600);
+        recorded.world.resource_mut::<RequestLimits>().max_hops += 5;
+        recorded.world.resource_mut::<PheromoneParams>().nest_step /= 2.;
+        run_until(&mut recorded, TICKS);
+
+        let recording = Recording::load(&path);
+        fs::remove_file(&path).unwrap();
+        let recording = recording.unwrap();
+        let changes: Vec<_> = recording
+            .interventions
+            .iter()
+            .map(|i| (i.tick, std::mem::discriminant(&i.change)))
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                (
+                    200,
+                    std::mem::discriminant(&Change::DecisionWeights(default()))
+                ),
+                (400, std::mem::discriminant(&Change::TickLength(0.))),
+                (600, std::mem::discriminant(&Change::Pheromones(default()))),
+                (600, std::mem::discriminant(&Change::Limits(default()))),
+            ]
+        );
+
+        let start = &recording.start;
+        let mut replayed = headless_app(start.config.clone());
+        replayed
+            .insert_resource(start.clock.clone())
+            .insert_resource(start.arena)
+            .insert_resource(start.arena_settings.clone())
+            .add_plugin(ReplayPlugin::Playback(recording));
+        replayed.update();
+        run_until(&mut replayed, TICKS);
+
+        let (recorded, replayed) = (&recorded.world, &replayed.world);
+        assert_eq!(
+            replayed.resource::<SimClock>().tick_length,
+            recorded.resource::<SimClock>().tick_length
+        );
+        assert_eq!(
+            replayed.resource::<SimRng>().word_pos(),
+            recorded.resource::<SimRng>().word_pos()
+        );
+        assert_eq!(
+            format!("{:?}", replayed.resource::<Metrics>()),
+            format!("{:?}", recorded.resource::<Metrics>())
+        );
+    }
+}
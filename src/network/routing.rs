@@ -0,0 +1,329 @@
+use bevy::{log, prelude::*};
+
+use super::{ant::Ant, nest::Nest, DecisionWeights};
+
+/// the policy ants use unless another one is picked
+pub const DEFAULT_ROUTING_POLICY: &str = "pheromone";
+
+/// How an ant picks the nest it hops to next.
+/// Policies are registered in [`RoutingPolicies`] and picked by name with
+/// [`DecisionWeights::policy`], so they can be swapped while the simulation runs.
+pub trait RoutingPolicy: Send + Sync + 'static {
+    fn name(&self) -> &str;
+
+    /// Chance of hopping to each of `candidates`, in the same order, from an ant at `loc`
+    /// sitting in `current`. The weights don't have to add up to 1, they're normalized
+    /// before sampling. Weights that are all zero, negative, not finite or not one per
+    /// candidate make the ant use [`DecisionWeights::fallback`] instead.
+    fn weights(
+        &self,
+        ant: &Ant,
+        loc: Vec2,
+        current: &Nest,
+        candidates: &[&Nest],
+        decision_weights: &DecisionWeights,
+    ) -> Vec<f32>;
+}
+
+/// Closer nests, nests with more pheromone towards the ant's target and nests the ant
+/// hasn't visited lately are more likely, each factor raised to its power in [`DecisionWeights`].
+pub struct PheromoneRouting;
+
+impl RoutingPolicy for PheromoneRouting {
+    fn name(&self) -> &str {
+        DEFAULT_ROUTING_POLICY
+    }
+
+    fn weights(
+        &self,
+        ant: &Ant,
+        loc: Vec2,
+        _current: &Nest,
+        candidates: &[&Nest],
+        decision_weights: &DecisionWeights,
+    ) -> Vec<f32> {
+        candidates
+            .iter()
+            .map(|nest| {
+                let distance_factor = loc.distance(nest.loc);
+
+                let pheromone_factor = nest.weights[ant.target.0].max(1.0);
+
+                // how recently we visited this nest
+                let visited_factor = ant
+                    .prev_nests
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, &c)| {
+                        if c == nest.id {
+                            // weight by index
+                            Some(1. / (i as f32 + 1.0))
+                        } else {
+                            None
+                        }
+                    })
+                    .sum::<f32>()
+                    // ensure no div by 0
+                    .max(1.0);
+
+                let factors = [
+                    (1.0 / distance_factor).powf(decision_weights.distance_pow),
+                    pheromone_factor.powf(decision_weights.pheromone_pow),
+                    // make it less likely to visit ones we've been too recently
+                    (1.0 / visited_factor).powf(decision_weights.visited_pow),
+                ];
+                factors
+                    .iter()
+                    .map(|f| if f.is_finite() { *f } else { f32::MAX })
+                    .product()
+            })
+            .collect()
+    }
+}
+
+/// every neighbor is equally likely, a baseline for other policies
+pub struct UniformRouting;
+
+impl RoutingPolicy for UniformRouting {
+    fn name(&self) -> &str {
+        "uniform"
+    }
+
+    fn weights(
+        &self,
+        _ant: &Ant,
+        _loc: Vec2,
+        _current: &Nest,
+        candidates: &[&Nest],
+        _decision_weights: &DecisionWeights,
+    ) -> Vec<f32> {
+        vec![1.0; candidates.len()]
+    }
+}
+
+/// every routing policy that can be picked, by name
+#[derive(Resource)]
+pub struct RoutingPolicies {
+    policies: Vec<Box<dyn RoutingPolicy>>,
+}
+
+impl Default for RoutingPolicies {
+    fn default() -> Self {
+        Self {
+            policies: vec![Box::new(PheromoneRouting), Box::new(UniformRouting)],
+        }
+    }
+}
+
+impl RoutingPolicies {
+    /// adds a policy, replacing the one with the same name if there is one
+    pub fn register(&mut self, policy: impl RoutingPolicy) {
+        self.policies.retain(|p| p.name() != policy.name());
+        self.policies.push(Box::new(policy));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn RoutingPolicy> {
+        self.policies
+            .iter()
+            .find(|p| p.name() == name)
+            .map(|p| p.as_ref())
+    }
+
+    /// the policy called `name`, or the default one if there isn't one
+    pub fn get_or_default(&self, name: &str) -> &dyn RoutingPolicy {
+        self.get(name)
+            .or_else(|| self.get(DEFAULT_ROUTING_POLICY))
+            .unwrap_or(&PheromoneRouting)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.policies.iter().map(|p| p.name())
+    }
+}
+
+/// a policy that isn't registered, i.e. a typo in the inspector, is reset to the default
+pub fn check_routing_policy(
+    mut decision_weights: ResMut<DecisionWeights>,
+    policies: Res<RoutingPolicies>,
+) {
+    if !decision_weights.is_changed() || policies.get(&decision_weights.policy).is_some() {
+        return;
+    }
+    log::warn!(
+        "unknown routing policy {:?}, expected one of {:?}, using {:?}",
+        decision_weights.policy,
+        policies.names().collect::<Vec<_>>(),
+        DEFAULT_ROUTING_POLICY
+    );
+    decision_weights.policy = DEFAULT_ROUTING_POLICY.to_string();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use rand::distributions::{Distribution, WeightedIndex};
+
+    use crate::SimRng;
+
+    use super::{
+        super::{nest::NestId, FallbackPolicy},
+        *,
+    };
+
+    const FALLBACKS: [FallbackPolicy; 3] = [
+        FallbackPolicy::Uniform,
+        FallbackPolicy::Nearest,
+        FallbackPolicy::Wait,
+    ];
+
+    fn nest(id: usize, x: f32) -> Nest {
+        Nest::new(NestId(id), Vec2::new(x, 0.), 4)
+    }
+
+    /// heading from nest 0 to nest 3
+    fn ant() -> Ant {
+        Ant::new(NestId(3), NestId(0), 4, &mut SimRng::seed_from_u64(0))
+    }
+
+    #[test]
+    fn fallbacks() {
+        let candidates = [
+            (NestId(1), Vec2::new(10., 0.)),
+            (NestId(2), Vec2::new(3., 0.)),
+            (NestId(3), Vec2::new(-5., 0.)),
+        ];
+        let mut rng = SimRng::seed_from_u64(1);
+        let nearest =
+            |from| FallbackPolicy::Nearest.choose(from, &candidates, &mut SimRng::seed_from_u64(1));
+        assert_eq!(nearest(Vec2::ZERO), Some(NestId(2)));
+        assert_eq!(nearest(Vec2::new(-4., 0.)), Some(NestId(3)));
+        assert_eq!(
+            FallbackPolicy::Wait.choose(Vec2::ZERO, &candidates, &mut rng),
+            None
+        );
+        let chosen: BTreeSet<NestId> = (0..100)
+            .filter_map(|_| FallbackPolicy::Uniform.choose(Vec2::ZERO, &candidates, &mut rng))
+            .collect();
+        assert_eq!(chosen.len(), candidates.len());
+        for fallback in FALLBACKS {
+            assert_eq!(
+                fallback.choose(Vec2::ZERO, &[], &mut rng),
+                None,
+                "{:?}",
+                fallback
+            );
+        }
+    }
+
+    #[test]
+    fn pheromone_routing_prefers_close_nests_not_visited_lately() {
+        let decision_weights = DecisionWeights::default();
+        let (current, near, far) = (nest(0, 0.), nest(1, 10.), nest(2, 100.));
+        let weights = PheromoneRouting.weights(
+            &ant(),
+            Vec2::ZERO,
+            &current,
+            &[&near, &far],
+            &decision_weights,
+        );
+        assert_eq!(weights.len(), 2);
+        assert!(weights[0] > weights[1], "{:?}", weights);
+
+        let mut visited = ant();
+        visited.prev_nests.push_front(NestId(1));
+        visited.prev_nests.push_front(NestId(1));
+        let revisited = PheromoneRouting.weights(
+            &visited,
+            Vec2::ZERO,
+            &current,
+            &[&near, &far],
+            &decision_weights,
+        );
+        assert!(revisited[0] < weights[0], "{:?} {:?}", revisited, weights);
+        assert_eq!(revisited[1], weights[1]);
+    }
+
+    #[test]
+    fn uniform_routing_weighs_every_neighbor_the_same() {
+        let (current, a, b, c) = (nest(0, 0.), nest(1, 1.), nest(2, 10.), nest(3, 100.));
+        let weights = UniformRouting.weights(
+            &ant(),
+            Vec2::ZERO,
+            &current,
+            &[&a, &b, &c],
+            &DecisionWeights::default(),
+        );
+        assert_eq!(weights, [1.; 3]);
+    }
+
+    #[test]
+    fn switching_policy_changes_the_hop() {
+        let policies = RoutingPolicies::default();
+        let (current, near, far) = (nest(0, 0.), nest(1, 1.), nest(2, 1000.));
+        // out of 1000 hops from the same seed
+        let hops_to_far = |decision_weights: &DecisionWeights| {
+            let weights = policies.get_or_default(&decision_weights.policy).weights(
+                &ant(),
+                Vec2::ZERO,
+                &current,
+                &[&near, &far],
+                decision_weights,
+            );
+            let dist = WeightedIndex::new(weights).unwrap();
+            let mut rng = SimRng::seed_from_u64(2);
+            (0..1000).filter(|_| dist.sample(&mut rng) == 1).count()
+        };
+        let mut decision_weights = DecisionWeights::default();
+        assert!(hops_to_far(&decision_weights) < 50);
+        decision_weights.policy = "uniform".to_string();
+        assert!(hops_to_far(&decision_weights) > 400);
+    }
+
+    #[test]
+    fn registered_policies_are_found_by_name() {
+        struct Farthest;
+
+        impl RoutingPolicy for Farthest {
+            fn name(&self) -> &str {
+                "uniform"
+            }
+
+            fn weights(
+                &self,
+                _ant: &Ant,
+                loc: Vec2,
+                _current: &Nest,
+                candidates: &[&Nest],
+                _decision_weights: &DecisionWeights,
+            ) -> Vec<f32> {
+                candidates.iter().map(|n| loc.distance(n.loc)).collect()
+            }
+        }
+
+        let mut policies = RoutingPolicies::default();
+        assert_eq!(
+            policies.names().collect::<Vec<_>>(),
+            ["pheromone", "uniform"]
+        );
+        assert_eq!(
+            policies.get_or_default("missing").name(),
+            DEFAULT_ROUTING_POLICY
+        );
+        // replaces the built in one
+        policies.register(Farthest);
+        assert_eq!(
+            policies.names().collect::<Vec<_>>(),
+            ["pheromone", "uniform"]
+        );
+        let (current, near, far) = (nest(0, 0.), nest(1, 1.), nest(2, 10.));
+        let weights = policies.get("uniform").unwrap().weights(
+            &ant(),
+            Vec2::ZERO,
+            &current,
+            &[&near, &far],
+            &DecisionWeights::default(),
+        );
+        assert_eq!(weights, [1., 10.]);
+    }
+}
@@ -0,0 +1,542 @@
+use std::{
+    collections::BTreeSet,
+    fmt,
+    fs::{self, File},
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+};
+
+use bevy::{app::AppExit, log, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    clock::SimClock,
+    config::{ConfigError, SimConfig},
+    BoundingBox, Colors, HexagonMesh, NumAnts, SimRng,
+};
+
+use super::{
+    ant::{Ant, AntBundle, AntPool, AntTexture, Pooled},
+    dot::EdgeTraffic,
+    metrics::Metrics,
+    nest::{self, Nest, NestId, NestIndex},
+    pheromones::{self, Pheromone, PheromoneManager},
+    topology::{Topology, TopologyParams},
+    NetworkParams, RequestStats,
+};
+
+/// where `F5` saves a snapshot to and `F9` loads it from
+pub const DEFAULT_SNAPSHOT_PATH: &str = "ant-sim-snapshot.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NestState {
+    pub id: NestId,
+    pub loc: [f32; 2],
+    /// pheromone strength towards each nest, indexed by nest id
+    pub weights: Vec<f32>,
+    #[serde(default)]
+    pub request_prob: Option<f32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AntState {
+    pub translation: [f32; 3],
+    pub ant: Ant,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PheromoneState {
+    /// cell of the pheromone grid
+    pub loc: [u32; 2],
+    /// trail strength towards each nest, indexed by nest id
+    pub weights: Vec<f32>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RngState {
+    pub seed: u64,
+    pub word_pos: u128,
+}
+
+/// Everything needed to pick the network simulation back up between two ticks, stored as json.
+/// Ants waiting in the pool and [`EdgeTraffic`] aren't saved, and requests already in flight
+/// when the snapshot is loaded count towards [`RequestStats`] but not [`Metrics`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// the parameters the simulation was running with, not the ones it started with
+    pub config: SimConfig,
+    pub clock: SimClock,
+    pub arena: BoundingBox,
+    pub rng: RngState,
+    pub stats: RequestStats,
+    /// in id order
+    pub nests: Vec<NestState>,
+    /// missing from snapshots saved before there were topologies, every nest is connected then
+    #[serde(default)]
+    pub topology: Option<Topology>,
+    /// in the order they were spawned
+    pub ants: Vec<AntState>,
+    pub pheromones: Vec<PheromoneState>,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// one message per problem
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "couldn't access snapshot: {}", e),
+            SnapshotError::Json(e) => write!(f, "couldn't parse snapshot: {}", e),
+            SnapshotError::Invalid(problems) => {
+                write!(f, "invalid snapshot:")?;
+                for problem in problems {
+                    write!(f, "\n  {}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> Self {
+        SnapshotError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for SnapshotError {
+    fn from(e: serde_json::Error) -> Self {
+        SnapshotError::Json(e)
+    }
+}
+
+impl Snapshot {
+    pub fn from_world(world: &mut World) -> Self {
+        let mut ants: Vec<(Entity, AntState)> = world
+            .query_filtered::<(Entity, &Ant, &Transform), Without<Pooled>>()
+            .iter(world)
+            .map(|(id, ant, transform)| {
+                let state = AntState {
+                    translation: transform.translation.to_array(),
+                    ant: ant.clone(),
+                };
+                (id, state)
+            })
+            .collect();
+        ants.sort_unstable_by_key(|(id, _)| *id);
+
+        let mut pheromones: Vec<PheromoneState> = world
+            .query::<&Pheromone>()
+            .iter(world)
+            .map(|pheromone| PheromoneState {
+                loc: pheromone.loc().to_array(),
+                weights: pheromone.weights.clone(),
+            })
+            .collect();
+        pheromones.sort_unstable_by_key(|p| (p.loc[1], p.loc[0]));
+
+        let nests = world
+            .resource::<NestIndex>()
+            .nests
+            .iter()
+            .map(|&id| {
+                let nest = world
+                    .get::<Nest>(id)
+                    .expect("nest index should only contain existing nests");
+                NestState {
+                    id: nest.id,
+                    loc: nest.loc.to_array(),
+                    weights: nest.weights.clone(),
+                    request_prob: nest.request_prob,
+                }
+            })
+            .collect();
+
+        let rng = world.resource::<SimRng>();
+        Self {
+            config: SimConfig::from_world(world),
+            rng: RngState {
+                seed: rng.seed(),
+                word_pos: rng.word_pos(),
+            },
+            clock: world.resource::<SimClock>().clone(),
+            arena: *world.resource::<BoundingBox>(),
+            stats: world.resource::<RequestStats>().clone(),
+            nests,
+            topology: Some(world.resource::<Topology>().clone()),
+            ants: ants.into_iter().map(|(_, state)| state).collect(),
+            pheromones,
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SnapshotError> {
+        let contents = fs::read_to_string(path)?;
+        let snapshot: Self = serde_json::from_str(&contents)?;
+        snapshot.validate()?;
+        Ok(snapshot)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        let out = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(out, self)?;
+        Ok(())
+    }
+
+    /// checks every nest id and grid cell refers to something that will exist once restored
+    pub fn validate(&self) -> Result<(), SnapshotError> {
+        let mut problems = match self.config.validate() {
+            Err(ConfigError::Invalid(problems)) => problems,
+            _ => Vec::new(),
+        };
+        let num_nests = self.nests.len();
+        if num_nests != self.config.network.num_nests {
+            problems.push(format!(
+                "network.num_nests is {} but there are {} nests",
+                self.config.network.num_nests, num_nests
+            ));
+        }
+        let positive = |v: f32| v.is_finite() && v > 0.;
+        if !positive(self.arena.w) || !positive(self.arena.h) {
+            problems.push(format!(
+                "arena must be positive (got {}x{})",
+                self.arena.w, self.arena.h
+            ));
+        }
+        for (i, nest) in self.nests.iter().enumerate() {
+            if nest.id != NestId(i) {
+                problems.push(format!("nest {} is stored as nest {}", nest.id, i));
+            }
+            if nest.weights.len() != num_nests {
+                problems.push(format!(
+                    "nest {} has {} weights for {} nests",
+                    nest.id,
+                    nest.weights.len(),
+                    num_nests
+                ));
+            }
+        }
+        if let Some(topology) = &self.topology {
+            problems.extend(topology.problems(num_nests));
+        }
+        for (i, AntState { ant, .. }) in self.ants.iter().enumerate() {
+            let mut ids = [ant.target, ant.parent]
+                .into_iter()
+                .chain(ant.current_nest)
+                .chain(ant.prev_nests.iter().copied());
+            if ant.prev_nests.is_empty() || ids.any(|id| id.0 >= num_nests) {
+                problems.push(format!("ant {} refers to a nest that doesn't exist", i));
+            }
+        }
+        let grid = PheromoneManager::new(
+            self.arena.w,
+            self.arena.h,
+            self.config.network.pheromone_granularity.max(1),
+        );
+        let mut cells = BTreeSet::new();
+        for pheromone in &self.pheromones {
+            // a second one would leave the first without a cell
+            if !cells.insert(pheromone.loc) {
+                problems.push(format!("more than one pheromone at {:?}", pheromone.loc));
+            }
+            if !grid.contains(UVec2::from(pheromone.loc)) {
+                problems.push(format!(
+                    "pheromone at {:?} is outside the grid",
+                    pheromone.loc
+                ));
+            }
+            if pheromone.weights.len() != num_nests {
+                problems.push(format!(
+                    "pheromone at {:?} has {} weights for {} nests",
+                    pheromone.loc,
+                    pheromone.weights.len(),
+                    num_nests
+                ));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(SnapshotError::Invalid(problems))
+        }
+    }
+
+    /// Replaces every nest, ant and pheromone, and the resources they depend on.
+    /// The snapshot should be valid, see [`Snapshot::validate`].
+    pub fn restore(self, world: &mut World) {
+        // pooled ants included, the pool starts out empty again
+        let spawned: Vec<Entity> = world
+            .query_filtered::<Entity, Or<(With<Ant>, With<Nest>, With<Pheromone>)>>()
+            .iter(world)
+            .collect();
+        for entity in spawned {
+            world.entity_mut(entity).despawn_recursive();
+        }
+
+        let num_nests = self.nests.len();
+        // without triggering a relayout, the nests are put back where they were
+        *world
+            .resource_mut::<BoundingBox>()
+            .bypass_change_detection() = self.arena;
+        world.insert_resource(self.config.network);
+        world.insert_resource(self.config.decision_weights);
+        world.insert_resource(self.config.pheromones);
+        world.insert_resource(self.config.limits);
+        // the saved topology is restored below rather than generated again
+        *world
+            .resource_mut::<TopologyParams>()
+            .bypass_change_detection() = self.config.topology;
+        world.insert_resource(self.clock);
+        world.insert_resource(SimRng::from_word_pos(self.rng.seed, self.rng.word_pos));
+        world.insert_resource(self.stats);
+        world.insert_resource(
+            self.topology
+                .unwrap_or_else(|| Topology::complete(num_nests)),
+        );
+        world.insert_resource(NumAnts(self.ants.len() as u32));
+        world.insert_resource(AntPool::default());
+        world.insert_resource(Metrics::default());
+        world.insert_resource(EdgeTraffic::default());
+        world.resource_mut::<Colors>().resize(num_nests);
+        let hex_mesh = world.resource::<HexagonMesh>().clone();
+        let ant_texture = world.resource::<AntTexture>().0.clone();
+
+        let nests: Vec<_> = world.resource_scope(|world, mut meshes: Mut<Assets<Mesh>>| {
+            let colors = world.resource::<Colors>();
+            self.nests
+                .into_iter()
+                .map(|state| {
+                    let mut nest = Nest::new(state.id, Vec2::from(state.loc), num_nests);
+                    nest.weights = state.weights;
+                    nest.request_prob = state.request_prob;
+                    nest::nest_bundle(nest, colors, &mut meshes)
+                })
+                .collect()
+        });
+        let nests = nests.into_iter().map(|b| world.spawn(b).id()).collect();
+        world.insert_resource(NestIndex { nests });
+
+        let granularity = world.resource::<NetworkParams>().pheromone_granularity;
+        let mut manager = PheromoneManager::new(self.arena.w, self.arena.h, granularity);
+        let colors = world.resource::<Colors>();
+        let pheromones: Vec<_> = self
+            .pheromones
+            .into_iter()
+            .map(|state| {
+                let loc = UVec2::from(state.loc);
+                let mut pheromone = Pheromone::new(num_nests, loc);
+                pheromone.weights = state.weights;
+                let color = colors.handle_of(pheromone.most_prominent());
+                let bundle = pheromones::pheromone_bundle(pheromone, color, &manager, &hex_mesh);
+                (loc, bundle)
+            })
+            .collect();
+        let ants: Vec<_> = self
+            .ants
+            .into_iter()
+            .map(|state| {
+                // the data it picked up at the target, which is now its parent
+                let food = state
+                    .ant
+                    .carrying_food
+                    .then(|| nest::food_bundle(state.ant.parent, colors, &hex_mesh));
+                let transform = Transform::from_translation(Vec3::from(state.translation));
+                (
+                    AntBundle::from_ant(state.ant, &transform, &ant_texture),
+                    food,
+                )
+            })
+            .collect();
+
+        let (manager_id, mut manager_transform) = world
+            .query_filtered::<(Entity, &mut Transform), With<PheromoneManager>>()
+            .get_single_mut(world)
+            .expect("there should be pheromones");
+        manager_transform.translation.x = -(self.arena.w / 2.0);
+        manager_transform.translation.y = -(self.arena.h / 2.0);
+        world.entity_mut(manager_id).with_children(|builder| {
+            for (loc, bundle) in pheromones {
+                manager[loc] = Some(builder.spawn(bundle).id());
+            }
+        });
+        world.entity_mut(manager_id).insert(manager);
+
+        for (ant, food) in ants {
+            let mut entity = world.spawn(ant);
+            if let Some(food) = food {
+                entity.with_children(|builder| {
+                    builder.spawn(food);
+                });
+            }
+        }
+    }
+}
+
+/// saves a snapshot to the file once the current tick is done
+#[derive(Debug, Clone)]
+pub struct SaveSnapshot(pub PathBuf);
+
+/// replaces the simulation with the snapshot in the file once the current tick is done
+#[derive(Debug, Clone)]
+pub struct LoadSnapshot(pub PathBuf);
+
+fn save(world: &mut World, path: &Path) {
+    match Snapshot::from_world(world).save(path) {
+        Ok(()) => log::info!("saved snapshot to {}", path.display()),
+        Err(e) => log::error!("couldn't save {}: {}", path.display(), e),
+    }
+}
+
+/// runs between ticks so a snapshot never holds half a tick
+pub fn handle_snapshots(world: &mut World) {
+    let saves: Vec<_> = world
+        .resource_mut::<Events<SaveSnapshot>>()
+        .drain()
+        .collect();
+    for SaveSnapshot(path) in saves {
+        save(world, &path);
+    }
+    let loads: Vec<_> = world
+        .resource_mut::<Events<LoadSnapshot>>()
+        .drain()
+        .collect();
+    for LoadSnapshot(path) in loads {
+        match Snapshot::load(&path) {
+            Ok(snapshot) => {
+                snapshot.restore(world);
+                log::info!("loaded snapshot from {}", path.display());
+            }
+            Err(e) => log::error!("couldn't load {}: {}", path.display(), e),
+        }
+    }
+}
+
+/// where to save a snapshot when the app exits
+#[derive(Debug, Clone, Resource)]
+pub struct SnapshotOnExit(pub PathBuf);
+
+pub fn save_on_exit(world: &mut World) {
+    if world.resource::<Events<AppExit>>().is_empty() {
+        return;
+    }
+    let path = world.resource::<SnapshotOnExit>().0.clone();
+    save(world, &path);
+}
+
+/// `F5` to save a snapshot, `F9` to load it again
+pub fn snapshot_controls(
+    keys: Res<Input<KeyCode>>,
+    mut save: EventWriter<SaveSnapshot>,
+    mut load: EventWriter<LoadSnapshot>,
+) {
+    if keys.just_pressed(KeyCode::F5) {
+        save.send(SaveSnapshot(DEFAULT_SNAPSHOT_PATH.into()));
+    }
+    if keys.just_pressed(KeyCode::F9) {
+        load.send(LoadSnapshot(DEFAULT_SNAPSHOT_PATH.into()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::batch::headless_app;
+
+    use super::*;
+
+    fn app(seed: u64) -> App {
+        let mut app = headless_app(SimConfig {
+            seed: Some(seed),
+            ..default()
+        });
+        // startup
+        app.update();
+        app
+    }
+
+    /// ants as json in a fixed order, restored ants can reuse any despawned entity
+    fn ants(snapshot: &Snapshot) -> Vec<String> {
+        let mut ants: Vec<String> = snapshot
+            .ants
+            .iter()
+            .map(|ant| serde_json::to_string(ant).unwrap())
+            .collect();
+        ants.sort();
+        ants
+    }
+
+    fn without_ants(snapshot: &Snapshot) -> serde_json::Value {
+        serde_json::to_value(Snapshot {
+            ants: Vec::new(),
+            ..snapshot.clone()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn restored_snapshot_matches_the_saved_one() {
+        let mut saved = app(7);
+        for _ in 0..600 {
+            saved.update();
+        }
+        let snapshot = Snapshot::from_world(&mut saved.world);
+        assert!(!snapshot.ants.is_empty());
+        assert!(!snapshot.pheromones.is_empty());
+
+        let path =
+            std::env::temp_dir().join(format!("ant-sim-snapshot-test-{}.json", std::process::id()));
+        snapshot.save(&path).unwrap();
+        let loaded = Snapshot::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        let mut restored = app(8);
+        loaded.unwrap().restore(&mut restored.world);
+        let world = &mut restored.world;
+        let again = Snapshot::from_world(world);
+        assert_eq!(without_ants(&again), without_ants(&snapshot));
+        assert_eq!(ants(&again), ants(&snapshot));
+
+        assert_eq!(
+            world.resource::<SimRng>().word_pos(),
+            saved.world.resource::<SimRng>().word_pos()
+        );
+        assert_eq!(
+            world.resource::<Topology>(),
+            saved.world.resource::<Topology>()
+        );
+        // pooled ants aren't saved
+        assert!(world.resource::<AntPool>().is_empty());
+        let num_ants = world.query::<&Ant>().iter(world).count();
+        assert_eq!(num_ants, snapshot.ants.len());
+        assert_eq!(**world.resource::<NumAnts>(), snapshot.ants.len() as u32);
+        let manager = world
+            .query::<&PheromoneManager>()
+            .single(world)
+            .filled_cells();
+        assert_eq!(manager, snapshot.pheromones.len());
+    }
+
+    #[test]
+    fn duplicate_pheromones_are_rejected() {
+        let mut saved = app(7);
+        for _ in 0..600 {
+            saved.update();
+        }
+        let mut snapshot = Snapshot::from_world(&mut saved.world);
+        let pheromone = snapshot.pheromones[0].clone();
+        snapshot.pheromones.push(pheromone.clone());
+        match snapshot.validate() {
+            Err(SnapshotError::Invalid(problems)) => assert_eq!(
+                problems,
+                [format!("more than one pheromone at {:?}", pheromone.loc)]
+            ),
+            other => panic!("expected an invalid snapshot, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,581 @@
+use std::collections::BTreeSet;
+
+use bevy::{log, prelude::*, sprite::MaterialMesh2dBundle};
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+
+use crate::{SimRng, BOARD_HEIGHT, NEST_HEIGHT};
+
+use super::{
+    import::ImportedNetwork,
+    nest::{Nest, NestId, NestIndex},
+    NetworkParams,
+};
+
+/// between the pheromones and the nests
+const EDGE_Z: f32 = (BOARD_HEIGHT + NEST_HEIGHT) as f32 / 2.;
+const EDGE_WIDTH: f32 = 1.5;
+const EDGE_COLOR: Color = Color::rgba(1., 1., 1., 0.15);
+
+/// Which nests are neighbors in the overlay, ants only ever hop along these edges.
+/// Edges go both ways and neighbors are kept in id order, so they're always weighed the same way.
+#[derive(Debug, Clone, PartialEq, Resource, Serialize, Deserialize)]
+pub struct Topology {
+    /// neighbors of each nest, indexed by nest id
+    neighbors: Vec<Vec<NestId>>,
+}
+
+impl Topology {
+    /// nests without any edges
+    pub fn empty(num_nests: usize) -> Self {
+        Self {
+            neighbors: vec![Vec::new(); num_nests],
+        }
+    }
+
+    /// every nest is a neighbor of every other nest
+    pub fn complete(num_nests: usize) -> Self {
+        let mut topology = Self::empty(0);
+        topology.grow(num_nests);
+        topology
+    }
+
+    pub fn num_nests(&self) -> usize {
+        self.neighbors.len()
+    }
+
+    /// connects two nests, returns false if they already were or `a` and `b` are the same nest
+    pub fn add_edge(&mut self, a: NestId, b: NestId) -> bool {
+        if a == b || self.contains_edge(a, b) {
+            return false;
+        }
+        for (from, to) in [(a, b), (b, a)] {
+            let neighbors = &mut self.neighbors[from.0];
+            let i = neighbors.partition_point(|&n| n < to);
+            neighbors.insert(i, to);
+        }
+        true
+    }
+
+    /// returns false if they weren't connected
+    pub fn remove_edge(&mut self, a: NestId, b: NestId) -> bool {
+        if !self.contains_edge(a, b) {
+            return false;
+        }
+        self.neighbors[a.0].retain(|&n| n != b);
+        self.neighbors[b.0].retain(|&n| n != a);
+        true
+    }
+
+    pub fn contains_edge(&self, a: NestId, b: NestId) -> bool {
+        self.neighbors[a.0].binary_search(&b).is_ok()
+    }
+
+    /// in id order
+    pub fn neighbors(&self, nest: NestId) -> &[NestId] {
+        &self.neighbors[nest.0]
+    }
+
+    /// every edge once, with the lower id first
+    pub fn edges(&self) -> impl Iterator<Item = (NestId, NestId)> + '_ {
+        self.neighbors
+            .iter()
+            .enumerate()
+            .flat_map(|(a, neighbors)| {
+                neighbors
+                    .iter()
+                    .filter(move |b| b.0 > a)
+                    .map(move |&b| (NestId(a), b))
+            })
+    }
+
+    pub fn num_edges(&self) -> usize {
+        self.neighbors.iter().map(Vec::len).sum::<usize>() / 2
+    }
+
+    /// adds nests up to `num_nests`, each connected to every nest before it
+    pub fn grow(&mut self, num_nests: usize) {
+        for new in self.num_nests()..num_nests {
+            self.neighbors.push(Vec::new());
+            for existing in 0..new {
+                self.add_edge(NestId(existing), NestId(new));
+            }
+        }
+    }
+
+    /// one message per edge that doesn't fit in a network of `num_nests`, i.e. from a file
+    pub fn problems(&self, num_nests: usize) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.num_nests() != num_nests {
+            problems.push(format!(
+                "topology has {} nests but there are {} nests",
+                self.num_nests(),
+                num_nests
+            ));
+        }
+        for (a, neighbors) in self.neighbors.iter().enumerate() {
+            let a = NestId(a);
+            if neighbors.windows(2).any(|w| w[0] >= w[1]) {
+                problems.push(format!("neighbors of nest {} aren't sorted", a));
+            }
+            for &b in neighbors {
+                if b.0 >= self.num_nests() {
+                    problems.push(format!("nest {} has neighbor {} which doesn't exist", a, b));
+                } else if a == b {
+                    problems.push(format!("nest {} is its own neighbor", a));
+                } else if !self.neighbors[b.0].contains(&a) {
+                    problems.push(format!("edge {}-{} only goes one way", a, b));
+                }
+            }
+        }
+        problems
+    }
+}
+
+/// How the edges between nests are generated.
+/// Only the edges are generated, the nests stay where they were laid out, so the ring and
+/// lattice of `small_world`, `ring` and `grid` are over nest ids and their edges can cross
+/// the arena, with distance telling ants nothing about how far along them a nest is.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Reflect, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TopologyKind {
+    /// every nest is a neighbor of every other nest
+    #[default]
+    Complete,
+    /// each nest is connected to its `k` closest nests
+    KNearest,
+    /// nests closer than `radius` are connected
+    Radius,
+    /// Watts-Strogatz: a ring where each nest is connected to its `k` closest nests by id,
+    /// with every edge moved to a random nest with a chance of `rewire_prob`
+    SmallWorld,
+    /// Barabási-Albert: nests are added one by one, each connected to `k` nests picked
+    /// by how many neighbors they already have
+    ScaleFree,
+    /// each nest is connected to the nests before and after it by id
+    Ring,
+    /// each nest is connected to the nests next to it in a lattice of ids, `columns` to a row
+    Grid,
+}
+
+/// The overlay the nests form, generated at startup and again whenever this changes.
+/// Generators that are random draw from [`SimRng`].
+#[derive(Debug, Clone, PartialEq, Resource, Reflect, Serialize, Deserialize)]
+#[reflect(Resource)]
+#[serde(default, deny_unknown_fields)]
+pub struct TopologyParams {
+    pub kind: TopologyKind,
+    /// neighbors of each nest for `k_nearest` and `small_world`, edges of each new nest for
+    /// `scale_free`
+    pub k: usize,
+    /// distance under which nests are neighbors for `radius`
+    pub radius: f32,
+    /// chance of each edge being moved for `small_world`
+    pub rewire_prob: f32,
+    /// nests per row for `grid`, 0 for as square as possible
+    pub columns: usize,
+}
+
+impl Default for TopologyParams {
+    fn default() -> Self {
+        Self {
+            kind: TopologyKind::default(),
+            k: 4,
+            radius: 200.,
+            rewire_prob: 0.1,
+            columns: 0,
+        }
+    }
+}
+
+impl TopologyParams {
+    /// a topology for nests at `locs`, indexed by nest id
+    pub fn generate(&self, locs: &[Vec2], rng: &mut impl Rng) -> Topology {
+        let num_nests = locs.len();
+        let mut topology = Topology::empty(num_nests);
+        let mut connect = |a: usize, b: usize| topology.add_edge(NestId(a), NestId(b));
+        match self.kind {
+            TopologyKind::Complete => return Topology::complete(num_nests),
+            TopologyKind::KNearest => {
+                for (a, from) in locs.iter().enumerate() {
+                    let mut others: Vec<usize> = (0..num_nests).filter(|&b| b != a).collect();
+                    // stable, so ties go to the lower id
+                    others.sort_by(|&b, &c| {
+                        from.distance(locs[b]).total_cmp(&from.distance(locs[c]))
+                    });
+                    for &b in others.iter().take(self.k) {
+                        connect(a, b);
+                    }
+                }
+            }
+            TopologyKind::Radius => {
+                for (a, from) in locs.iter().enumerate() {
+                    for (b, to) in locs.iter().enumerate().skip(a + 1) {
+                        if from.distance(*to) <= self.radius {
+                            connect(a, b);
+                        }
+                    }
+                }
+            }
+            TopologyKind::SmallWorld => {
+                let half = (self.k / 2).max(1);
+                for a in 0..num_nests {
+                    for step in 1..=half {
+                        connect(a, (a + step) % num_nests);
+                    }
+                }
+                for a in (0..num_nests).map(NestId) {
+                    for step in 1..=half {
+                        let b = NestId((a.0 + step) % num_nests);
+                        if !topology.contains_edge(a, b) || rng.gen::<f32>() >= self.rewire_prob {
+                            continue;
+                        }
+                        let free: Vec<NestId> = (0..num_nests)
+                            .map(NestId)
+                            .filter(|&c| c != a && !topology.contains_edge(a, c))
+                            .collect();
+                        if let Some(&c) = free.choose(rng) {
+                            topology.remove_edge(a, b);
+                            topology.add_edge(a, c);
+                        }
+                    }
+                }
+            }
+            TopologyKind::ScaleFree => {
+                let per_nest = self.k.max(1);
+                let start = (per_nest + 1).min(num_nests);
+                for a in 0..start {
+                    for b in a + 1..start {
+                        connect(a, b);
+                    }
+                }
+                // every nest once per neighbor, so picking from it favors well connected nests
+                let mut ends: Vec<NestId> = topology.edges().flat_map(|(a, b)| [a, b]).collect();
+                for new in start..num_nests {
+                    let mut targets = BTreeSet::new();
+                    while targets.len() < per_nest.min(new) {
+                        let target = match ends.choose(rng) {
+                            Some(&target) => target,
+                            None => NestId(rng.gen_range(0..new)),
+                        };
+                        targets.insert(target);
+                    }
+                    for target in targets {
+                        topology.add_edge(NestId(new), target);
+                        ends.extend([NestId(new), target]);
+                    }
+                }
+            }
+            TopologyKind::Ring => {
+                for a in 0..num_nests {
+                    connect(a, (a + 1) % num_nests);
+                }
+            }
+            TopologyKind::Grid => {
+                let columns = match self.columns {
+                    0 => (num_nests as f32).sqrt().ceil() as usize,
+                    columns => columns,
+                };
+                for a in 0..num_nests {
+                    if (a + 1) % columns != 0 && a + 1 < num_nests {
+                        connect(a, a + 1);
+                    }
+                    if a + columns < num_nests {
+                        connect(a, a + columns);
+                    }
+                }
+            }
+        }
+        topology
+    }
+}
+
+/// Generates the topology again when its parameters change, i.e. from the inspector.
+/// Nests added while running are given a new topology by
+/// [`add_nests`](super::nest::add_nests) instead.
+/// The edges of an [`ImportedNetwork`] are kept.
+pub fn regenerate_topology(
+    params: Res<TopologyParams>,
+    network: Res<NetworkParams>,
+    nest_ids: Res<NestIndex>,
+    nests: Query<&Nest>,
+    mut topology: ResMut<Topology>,
+    mut rng: ResMut<SimRng>,
+    imported: Option<Res<ImportedNetwork>>,
+) {
+    if !params.is_changed() || params.is_added() || network.num_nests > nest_ids.nests.len() {
+        return;
+    }
+    if imported.is_some() {
+        log::warn!("can't generate a topology for an imported network, keeping its edges");
+        return;
+    }
+    let locs: Vec<Vec2> = nest_ids
+        .nests
+        .iter()
+        .map(|&id| {
+            nests
+                .get(id)
+                .expect("nest index should only contain existing nests")
+                .loc
+        })
+        .collect();
+    *topology = params.generate(&locs, &mut *rng);
+    log::info!(
+        "generated {:?} topology with {} edges",
+        params.kind,
+        topology.num_edges()
+    );
+}
+
+/// drawn between two neighboring nests
+#[derive(Debug, Component)]
+pub struct Edge(pub NestId, pub NestId);
+
+#[derive(Resource)]
+pub struct EdgeAssets {
+    mesh: Handle<Mesh>,
+    material: Handle<ColorMaterial>,
+}
+
+impl FromWorld for EdgeAssets {
+    fn from_world(world: &mut World) -> Self {
+        let mesh = world
+            .resource_mut::<Assets<Mesh>>()
+            .add(shape::Quad::new(Vec2::ONE).into());
+        let material = world
+            .resource_mut::<Assets<ColorMaterial>>()
+            .add(ColorMaterial::from(EDGE_COLOR));
+        Self { mesh, material }
+    }
+}
+
+/// a unit quad stretched from one nest to the other
+fn edge_transform(from: Vec2, to: Vec2) -> Transform {
+    let delta = to - from;
+    Transform::from_translation(((from + to) / 2.).extend(EDGE_Z))
+        .with_rotation(Quat::from_rotation_z(delta.y.atan2(delta.x)))
+        .with_scale(Vec3::new(delta.length(), EDGE_WIDTH, 1.))
+}
+
+/// Keeps a quad for every edge of the topology, following the nests when they move.
+/// The quads are only replaced once every nest of the changed topology has been spawned.
+pub fn draw_edges(
+    mut commands: Commands,
+    topology: Res<Topology>,
+    assets: Res<EdgeAssets>,
+    nest_ids: Res<NestIndex>,
+    nests: Query<&Nest>,
+    mut edges: Query<(Entity, &Edge, &mut Transform)>,
+    mut stale: Local<bool>,
+) {
+    let loc = |id: NestId| {
+        let entity = nest_ids.nests.get(id.0)?;
+        nests.get(*entity).ok().map(|nest| nest.loc)
+    };
+    *stale |= topology.is_changed();
+    if *stale {
+        let ends: Option<Vec<_>> = topology
+            .edges()
+            .map(|(a, b)| Some((a, b, loc(a)?, loc(b)?)))
+            .collect();
+        let ends = match ends {
+            Some(ends) => ends,
+            None => return,
+        };
+        for (entity, ..) in &edges {
+            commands.entity(entity).despawn();
+        }
+        for (a, b, from, to) in ends {
+            commands.spawn((
+                MaterialMesh2dBundle {
+                    mesh: assets.mesh.clone().into(),
+                    material: assets.material.clone(),
+                    transform: edge_transform(from, to),
+                    ..default()
+                },
+                Edge(a, b),
+            ));
+        }
+        *stale = false;
+        return;
+    }
+    for (_, &Edge(a, b), mut transform) in &mut edges {
+        if let (Some(from), Some(to)) = (loc(a), loc(b)) {
+            let moved = edge_transform(from, to);
+            if *transform != moved {
+                *transform = moved;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [TopologyKind; 7] = [
+        TopologyKind::Complete,
+        TopologyKind::KNearest,
+        TopologyKind::Radius,
+        TopologyKind::SmallWorld,
+        TopologyKind::ScaleFree,
+        TopologyKind::Ring,
+        TopologyKind::Grid,
+    ];
+
+    /// scattered but reproducible, with no two nests in the same place
+    fn locs(num_nests: usize) -> Vec<Vec2> {
+        (0..num_nests)
+            .map(|i| Vec2::new((i * 37 % 101) as f32, (i * 59 % 103) as f32) * 10.)
+            .collect()
+    }
+
+    fn generate(params: &TopologyParams, num_nests: usize, seed: u64) -> Topology {
+        params.generate(&locs(num_nests), &mut SimRng::seed_from_u64(seed))
+    }
+
+    fn params(kind: TopologyKind) -> TopologyParams {
+        TopologyParams { kind, ..default() }
+    }
+
+    #[test]
+    fn every_kind_is_consistent() {
+        for kind in KINDS {
+            for num_nests in [0, 1, 2, 3, 10, 50] {
+                let topology = generate(&params(kind), num_nests, 1);
+                assert_eq!(
+                    topology.problems(num_nests),
+                    Vec::<String>::new(),
+                    "{:?} with {} nests",
+                    kind,
+                    num_nests
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn same_seed_same_topology() {
+        for kind in KINDS {
+            let params = TopologyParams {
+                kind,
+                rewire_prob: 0.5,
+                ..default()
+            };
+            assert_eq!(generate(&params, 40, 7), generate(&params, 40, 7));
+        }
+    }
+
+    #[test]
+    fn k_nearest_connects_each_nest_to_its_closest() {
+        for k in [1, 3, 4] {
+            let params = TopologyParams {
+                k,
+                ..params(TopologyKind::KNearest)
+            };
+            let locs = locs(30);
+            let topology = params.generate(&locs, &mut SimRng::seed_from_u64(0));
+            for (a, from) in locs.iter().enumerate() {
+                let a = NestId(a);
+                // edges go both ways, so nests can end up with more than k
+                assert!(topology.neighbors(a).len() >= k);
+                let closest = locs
+                    .iter()
+                    .enumerate()
+                    .filter(|&(b, _)| b != a.0)
+                    .min_by(|(_, b), (_, c)| from.distance(**b).total_cmp(&from.distance(**c)))
+                    .map(|(b, _)| NestId(b))
+                    .unwrap();
+                assert!(topology.contains_edge(a, closest));
+            }
+        }
+        // fewer nests than k connects everything
+        let topology = generate(&params(TopologyKind::KNearest), 3, 0);
+        assert_eq!(topology, Topology::complete(3));
+    }
+
+    #[test]
+    fn ring_edge_counts() {
+        for (num_nests, edges) in [(0, 0), (1, 0), (2, 1), (3, 3), (10, 10)] {
+            let topology = generate(&params(TopologyKind::Ring), num_nests, 0);
+            assert_eq!(topology.num_edges(), edges, "{} nests", num_nests);
+        }
+    }
+
+    #[test]
+    fn grid_edge_counts() {
+        for (num_nests, columns, edges) in [
+            (0, 0, 0),
+            (1, 0, 0),
+            (2, 0, 1),
+            (9, 0, 12),
+            // rows of 4, 4 and 2
+            (10, 0, 13),
+            (10, 5, 13),
+            (10, 1, 9),
+            (3, 10, 2),
+        ] {
+            let params = TopologyParams {
+                columns,
+                ..params(TopologyKind::Grid)
+            };
+            let topology = generate(&params, num_nests, 0);
+            assert_eq!(
+                topology.num_edges(),
+                edges,
+                "{} nests in {} columns",
+                num_nests,
+                columns
+            );
+        }
+    }
+
+    #[test]
+    fn small_world_keeps_the_edge_count() {
+        for rewire_prob in [0., 0.1, 0.5, 1.] {
+            for seed in 0..5 {
+                let params = TopologyParams {
+                    k: 4,
+                    rewire_prob,
+                    ..params(TopologyKind::SmallWorld)
+                };
+                let topology = generate(&params, 20, seed);
+                assert_eq!(topology.num_edges(), 40);
+            }
+        }
+        let lattice = TopologyParams {
+            rewire_prob: 0.,
+            ..params(TopologyKind::SmallWorld)
+        };
+        let mut ring = generate(&lattice, 20, 0);
+        for a in 0..20 {
+            assert!(ring.remove_edge(NestId(a), NestId((a + 2) % 20)));
+        }
+        assert_eq!(ring, generate(&params(TopologyKind::Ring), 20, 0));
+    }
+
+    #[test]
+    fn scale_free_gives_every_nest_its_edges() {
+        for k in [1, 2, 4, 10] {
+            for num_nests in [0, 1, 2, 5, 50] {
+                for seed in 0..3 {
+                    let params = TopologyParams {
+                        k,
+                        ..params(TopologyKind::ScaleFree)
+                    };
+                    let topology = generate(&params, num_nests, seed);
+                    for a in 0..num_nests {
+                        assert!(
+                            topology.neighbors(NestId(a)).len() >= k.min(a),
+                            "nest {} of {} with k {}",
+                            a,
+                            num_nests,
+                            k
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
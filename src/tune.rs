@@ -0,0 +1,255 @@
+use std::{
+    f32::consts::TAU,
+    io::{self, Write},
+};
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::Serialize;
+
+use crate::{
+    batch::{run_headless, RunSummary},
+    config::{ConfigError, SimConfig},
+    network::{DecisionWeights, PheromoneParams},
+};
+
+/// what the tuner tries to make as small as possible
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Objective {
+    /// mean hops from origin to target of delivered requests
+    MeanHops,
+    /// fraction of requests that failed, i.e. weren't delivered within the request limits
+    Failures,
+}
+
+impl Objective {
+    /// averaged over every run, runs without a single delivery count as infinitely bad
+    pub fn cost(&self, runs: &[RunSummary]) -> f32 {
+        let costs = runs.iter().map(|run| match self {
+            Objective::MeanHops => run.mean_hops.unwrap_or(f32::INFINITY),
+            Objective::Failures if run.delivered == 0 => f32::INFINITY,
+            Objective::Failures => 1. - run.delivery_rate,
+        });
+        costs.sum::<f32>() / runs.len().max(1) as f32
+    }
+}
+
+/// a tuned value, by its dotted config key, and the range it's searched in
+struct Param {
+    key: &'static str,
+    min: f32,
+    max: f32,
+}
+
+const PARAMS: [Param; 7] = [
+    Param {
+        key: "decision_weights.distance_pow",
+        min: 0.,
+        max: 8.,
+    },
+    Param {
+        key: "decision_weights.pheromone_pow",
+        min: 0.,
+        max: 8.,
+    },
+    Param {
+        key: "decision_weights.visited_pow",
+        min: 0.,
+        max: 8.,
+    },
+    Param {
+        key: "pheromones.trail_step",
+        min: 0.,
+        max: 0.5,
+    },
+    Param {
+        key: "pheromones.nest_step",
+        min: 0.,
+        max: 0.5,
+    },
+    Param {
+        key: "pheromones.trail_fade_rate",
+        min: 0.,
+        max: 0.6,
+    },
+    Param {
+        key: "pheromones.nest_fade_rate",
+        min: 0.,
+        max: 6.,
+    },
+];
+
+type Genome = [f32; PARAMS.len()];
+
+fn genome_of(config: &SimConfig) -> Genome {
+    let (w, p) = (&config.decision_weights, &config.pheromones);
+    [
+        w.distance_pow,
+        w.pheromone_pow,
+        w.visited_pow,
+        p.trail_step,
+        p.nest_step,
+        p.trail_fade_rate,
+        p.nest_fade_rate,
+    ]
+}
+
+fn config_of(base: &SimConfig, genome: &Genome) -> Result<SimConfig, ConfigError> {
+    let mut config = base.clone();
+    for (param, &value) in PARAMS.iter().zip(genome) {
+        config.set(param.key, toml::Value::Float(value as f64))?;
+    }
+    Ok(config)
+}
+
+/// standard normal sample, Box-Muller
+fn gaussian(rng: &mut impl Rng) -> f32 {
+    let u: f32 = 1. - rng.gen::<f32>();
+    let v: f32 = rng.gen();
+    (-2. * u.ln()).sqrt() * (TAU * v).cos()
+}
+
+/// How the search is run. Every candidate is simulated once per seed for `duration`
+/// simulated seconds, so a full search runs `generations * population * seeds` simulations.
+#[derive(Debug, Clone)]
+pub struct TuneSettings {
+    pub objective: Objective,
+    pub generations: usize,
+    pub population: usize,
+    pub seeds: Vec<u64>,
+    pub duration: f32,
+    /// starting mutation size, as a fraction of each parameter's range
+    pub mutation: f32,
+    /// seed of the search itself, separate from the simulations it runs
+    pub seed: u64,
+}
+
+#[derive(Debug, Clone)]
+struct Candidate {
+    genome: Genome,
+    cost: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Generation {
+    pub best_cost: f32,
+    pub mean_cost: f32,
+    pub mutation: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct TuneResult {
+    /// the base config with the best parameters found
+    pub best: SimConfig,
+    pub best_cost: f32,
+    /// one per generation
+    pub history: Vec<Generation>,
+}
+
+impl TuneResult {
+    /// the tuned sections, ready to paste into `ant-sim.toml`
+    pub fn best_toml(&self) -> String {
+        #[derive(Serialize)]
+        struct Tuned<'a> {
+            decision_weights: &'a DecisionWeights,
+            pheromones: &'a PheromoneParams,
+        }
+        toml::to_string(&Tuned {
+            decision_weights: &self.best.decision_weights,
+            pheromones: &self.best.pheromones,
+        })
+        .expect("parameters should always serialize")
+    }
+
+    pub fn write_history_csv(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "generation,best_cost,mean_cost,mutation")?;
+        for (i, generation) in self.history.iter().enumerate() {
+            writeln!(
+                out,
+                "{},{},{},{}",
+                i, generation.best_cost, generation.mean_cost, generation.mutation
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A (μ + λ) evolution strategy over the decision weights and pheromone rates.
+/// The better half of every generation survives and the other half is replaced by
+/// mutated copies of survivors, with mutations shrinking as the search goes on.
+/// The starting population is `base` plus random points in each parameter's range.
+pub fn tune(
+    base: &SimConfig,
+    settings: &TuneSettings,
+    mut progress: impl FnMut(usize, &Generation),
+) -> Result<TuneResult, ConfigError> {
+    let mut rng = ChaCha8Rng::seed_from_u64(settings.seed);
+    let population = settings.population.max(2);
+    let survivors = population / 2;
+    let mut mutation = settings.mutation;
+
+    let evaluate = |genome: Genome| -> Result<Candidate, ConfigError> {
+        let config = config_of(base, &genome)?;
+        let runs: Vec<RunSummary> = settings
+            .seeds
+            .iter()
+            .map(|&seed| {
+                let config = SimConfig {
+                    seed: Some(seed),
+                    ..config.clone()
+                };
+                run_headless(config, settings.duration)
+            })
+            .collect();
+        Ok(Candidate {
+            genome,
+            cost: settings.objective.cost(&runs),
+        })
+    };
+
+    let mut candidates = vec![evaluate(genome_of(base))?];
+    while candidates.len() < population {
+        let genome = PARAMS.map(|p| rng.gen_range(p.min..=p.max));
+        candidates.push(evaluate(genome)?);
+    }
+
+    let mut history = Vec::with_capacity(settings.generations);
+    for generation in 0..settings.generations {
+        candidates.sort_by(|a, b| a.cost.total_cmp(&b.cost));
+        let finite: Vec<f32> = candidates
+            .iter()
+            .map(|c| c.cost)
+            .filter(|c| c.is_finite())
+            .collect();
+        let stats = Generation {
+            best_cost: candidates[0].cost,
+            mean_cost: finite.iter().sum::<f32>() / finite.len().max(1) as f32,
+            mutation,
+        };
+        progress(generation, &stats);
+        history.push(stats);
+
+        if generation + 1 == settings.generations {
+            break;
+        }
+        candidates.truncate(survivors);
+        for i in 0..population - survivors {
+            let parent = &candidates[i % survivors].genome;
+            let mut genome = *parent;
+            for (value, param) in genome.iter_mut().zip(&PARAMS) {
+                let step = gaussian(&mut rng) * mutation * (param.max - param.min);
+                *value = (*value + step).clamp(param.min, param.max);
+            }
+            candidates.push(evaluate(genome)?);
+        }
+        mutation *= 0.9;
+    }
+
+    candidates.sort_by(|a, b| a.cost.total_cmp(&b.cost));
+    let best = &candidates[0];
+    Ok(TuneResult {
+        best: config_of(base, &best.genome)?,
+        best_cost: best.cost,
+        history,
+    })
+}